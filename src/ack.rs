@@ -0,0 +1,189 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use tokio::sync::oneshot;
+
+use crate::Serial;
+
+const INITIAL_RETRY_INTERVAL: Duration = Duration::from_millis(150);
+const MAX_ATTEMPTS: u32 = 5;
+
+// final outcome of a request that asked for an acknowledgement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    Failed,
+}
+
+struct PendingAck {
+    packet: Vec<u8>,
+    target: String,
+    interface: Option<String>,
+
+    attempts: u32,
+    retry_at: Instant,
+
+    // more than one when newer requests replaced older, unsent ones
+    results: Vec<oneshot::Sender<Delivery>>,
+}
+
+impl PendingAck {
+    fn finish(self, delivery: Delivery) {
+        for result in self.results {
+            let _ = result.send(delivery);
+        }
+    }
+}
+
+// tracks sent packets awaiting an Acknowledgement, keyed by (target serial, sequence).
+// our source id is the same on every packet, so the serial is what tells replies to equal sequences apart
+pub struct AckTracker {
+    pending: HashMap<(Serial, u8), PendingAck>,
+}
+
+impl AckTracker {
+    pub fn new() -> Self {
+        AckTracker {
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn track(&mut self, serial: Serial, sequence: u8, packet: Vec<u8>, target: String, interface: Option<String>, results: Vec<oneshot::Sender<Delivery>>) {
+        let pending = PendingAck {
+            packet,
+            target,
+            interface,
+            attempts: 1,
+            retry_at: Instant::now() + INITIAL_RETRY_INTERVAL,
+            results,
+        };
+
+        // the sequence number wrapped around before the old request was acknowledged
+        if let Some(replaced) = self.pending.insert((serial, sequence), pending) {
+            log::warn!("Sequence {} reused before it was acknowledged, giving up on previous request to {}", sequence, replaced.target);
+            replaced.finish(Delivery::Failed);
+        }
+    }
+
+    pub fn acknowledge(&mut self, serial: Serial, sequence: u8) -> bool {
+        match self.pending.remove(&(serial, sequence)) {
+            Some(pending) => {
+                log::debug!("Request {} to {} acknowledged after {} attempt(s)", sequence, pending.target, pending.attempts);
+                pending.finish(Delivery::Delivered);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_retry_at(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.retry_at).min()
+    }
+
+    // returns the packets that need to be sent again, failing any that have run out of attempts.
+    // slot claims a send for the light from the rate limiter, or says when the light can next be sent to
+    pub fn due_retransmissions(&mut self, now: Instant, mut slot: impl FnMut(Serial) -> Result<(), Instant>) -> Vec<(Vec<u8>, String, Option<String>)> {
+        let mut retransmissions = Vec::new();
+        let mut failed = Vec::new();
+
+        for (key, pending) in self.pending.iter_mut() {
+            if pending.retry_at > now {
+                continue;
+            }
+
+            if pending.attempts >= MAX_ATTEMPTS {
+                failed.push(*key);
+                continue;
+            }
+
+            if let Err(retry_at) = slot(key.0) {
+                pending.retry_at = retry_at;
+                continue;
+            }
+
+            pending.attempts += 1;
+            pending.retry_at = now + INITIAL_RETRY_INTERVAL * 2u32.pow(pending.attempts - 1);
+
+            retransmissions.push((pending.packet.clone(), pending.target.clone(), pending.interface.clone()));
+        }
+
+        for key in failed {
+            if let Some(pending) = self.pending.remove(&key) {
+                log::warn!("Request {} to {} was not acknowledged after {} attempts", key.1, pending.target, pending.attempts);
+                pending.finish(Delivery::Failed);
+            }
+        }
+
+        retransmissions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(tracker: &mut AckTracker, serial: Serial, sequence: u8) -> oneshot::Receiver<Delivery> {
+        let (tx, rx) = oneshot::channel();
+        tracker.track(serial, sequence, vec![0], "192.168.1.20:56700".to_string(), None, vec![tx]);
+
+        rx
+    }
+
+    #[test]
+    fn acknowledgement_from_another_light_is_ignored() {
+        let mut tracker = AckTracker::new();
+        let mut delivery = track(&mut tracker, Serial([1; 6]), 7);
+
+        assert!(!tracker.acknowledge(Serial([2; 6]), 7));
+        assert!(delivery.try_recv().is_err());
+
+        assert!(tracker.acknowledge(Serial([1; 6]), 7));
+        assert_eq!(delivery.try_recv(), Ok(Delivery::Delivered));
+    }
+
+    #[test]
+    fn same_sequence_to_different_lights_is_tracked_separately() {
+        let mut tracker = AckTracker::new();
+        let mut first = track(&mut tracker, Serial([1; 6]), 7);
+        let mut second = track(&mut tracker, Serial([2; 6]), 7);
+
+        assert!(tracker.acknowledge(Serial([2; 6]), 7));
+        assert_eq!(second.try_recv(), Ok(Delivery::Delivered));
+        assert!(first.try_recv().is_err());
+    }
+
+    #[test]
+    fn unacknowledged_request_fails_after_its_attempts() {
+        let mut tracker = AckTracker::new();
+        let mut delivery = track(&mut tracker, Serial([1; 6]), 7);
+
+        let mut now = Instant::now();
+        for _ in 1..MAX_ATTEMPTS {
+            now += Duration::from_secs(10);
+            assert_eq!(tracker.due_retransmissions(now, |_| Ok(())).len(), 1);
+        }
+
+        now += Duration::from_secs(10);
+        assert!(tracker.due_retransmissions(now, |_| Ok(())).is_empty());
+        assert_eq!(delivery.try_recv(), Ok(Delivery::Failed));
+    }
+
+    #[test]
+    fn rate_limited_retransmission_waits_without_using_an_attempt() {
+        let mut tracker = AckTracker::new();
+        let mut delivery = track(&mut tracker, Serial([1; 6]), 1);
+
+        let mut now = Instant::now() + Duration::from_secs(10);
+        let limited_until = now + Duration::from_millis(50);
+
+        assert!(tracker.due_retransmissions(now, |_| Err(limited_until)).is_empty());
+        assert!(tracker.due_retransmissions(now, |_| Ok(())).is_empty());
+
+        for _ in 1..MAX_ATTEMPTS {
+            now += Duration::from_secs(10);
+            assert_eq!(tracker.due_retransmissions(now, |_| Ok(())).len(), 1);
+        }
+
+        assert_eq!(delivery.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+    }
+}
@@ -0,0 +1,314 @@
+use axum::{extract::State, routing::{get, patch, post}, Router};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::{
+    color::{ColorChange, KELVIN_MAX, KELVIN_MIN}, error::ApiError, extract::{Json, OptionalJson, Path, Query}, groups::Membership, openapi, routes::{color_change, light_address, light_snapshot, set_light_color, set_light_power},
+    scenes::{Scene, SceneLight}, web::AppState, now_ms, Light, LightStatus, Serial,
+};
+
+// the stable, resource oriented API under /api/v1, described by the document at /api/v1/openapi.json
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/lights", get(list_lights))
+        .route("/lights/:id", get(get_light))
+        .route("/lights/:id/state", patch(update_state))
+        .route("/scenes", get(list_scenes).post(create_scene))
+        .route("/scenes/:name", get(get_scene).patch(update_scene).delete(delete_scene))
+        .route("/scenes/:name/recall", post(recall_scene))
+        .route("/openapi.json", get(openapi_document))
+}
+
+#[derive(Deserialize)]
+pub struct LightFilter {
+    // case insensitive, matching the whole label
+    label: Option<String>,
+    // the group or location label, or its id
+    group: Option<String>,
+    location: Option<String>,
+    online: Option<bool>,
+}
+
+impl LightFilter {
+    fn matches(&self, light: &Light) -> bool {
+        let matches_text = |filter: &Option<String>, value: Option<&str>| match filter {
+            Some(filter) => value.is_some_and(|value| value.eq_ignore_ascii_case(filter)),
+            None => true,
+        };
+
+        let matches_membership = |filter: &Option<String>, membership: Option<&Membership>| match filter {
+            Some(filter) => membership.is_some_and(|membership| {
+                membership.label.eq_ignore_ascii_case(filter) || membership.id.to_string().eq_ignore_ascii_case(filter)
+            }),
+            None => true,
+        };
+
+        matches_text(&self.label, light.label.as_deref())
+            && matches_membership(&self.group, light.group.as_ref())
+            && matches_membership(&self.location, light.location.as_ref())
+            && self.online.map_or(true, |online| (light.status == LightStatus::Online) == online)
+    }
+}
+
+pub async fn list_lights(state: State<AppState>, filter: Query<LightFilter>) -> Json<Vec<Light>> {
+    let lights = state.lights.read().await;
+
+    let mut matching = Vec::new();
+
+    for light in lights.values() {
+        let light = light.read().await;
+
+        if filter.matches(&light) {
+            matching.push(light.clone());
+        }
+    }
+
+    drop(lights);
+
+    matching.sort_by_key(|light| light.serial.0);
+
+    Json(matching)
+}
+
+pub async fn get_light(state: State<AppState>, Path(id): Path<Serial>) -> Result<Json<Light>, ApiError> {
+    light_snapshot(&state, &id).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerState {
+    On,
+    Off,
+}
+
+// every field is optional, whatever is left out stays as it is
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateChange {
+    power: Option<PowerState>,
+
+    // a color string like "#ff8800" or "2700K at 40%", see color::ColorChange
+    color: Option<ColorChange>,
+
+    hue: Option<u16>,
+    saturation: Option<u16>,
+    brightness: Option<u16>,
+    kelvin: Option<u16>,
+
+    duration_ms: Option<u32>,
+}
+
+// the color is set before the light is turned on, so it doesn't come on showing the old one
+pub async fn update_state(state: State<AppState>, Path(id): Path<Serial>, Json(change): Json<StateChange>) -> Result<Json<Light>, ApiError> {
+    log::debug!("State change request for {}", id);
+
+    let has_color = change.color.is_some()
+        || change.hue.is_some()
+        || change.saturation.is_some()
+        || change.brightness.is_some()
+        || change.kelvin.is_some();
+
+    if !has_color && change.power.is_none() {
+        return Err(ApiError::InvalidValue("a state change needs a power or color".to_string()));
+    }
+
+    if has_color {
+        let color = color_change(change.color, change.hue, change.saturation, change.brightness, change.kelvin)?;
+
+        set_light_color(&state, id, color, change.duration_ms).await?;
+    }
+
+    if let Some(power) = change.power {
+        let address = light_address(&state, id).await?;
+
+        set_light_power(&state, id, address, power == PowerState::On, change.duration_ms).await?;
+    }
+
+    light_snapshot(&state, &id).await
+}
+
+fn scene_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(ApiError::InvalidValue("a scene needs a name".to_string()));
+    }
+
+    Ok(name.to_string())
+}
+
+// the cached power and color of each light, which only exist once the light has answered a poll
+async fn capture_lights(state: &AppState, serials: &[Serial]) -> Result<Vec<SceneLight>, ApiError> {
+    if serials.is_empty() {
+        return Err(ApiError::InvalidValue("a scene needs at least one light".to_string()));
+    }
+
+    let lights = state.lights.read().await;
+
+    let mut captured = Vec::with_capacity(serials.len());
+
+    for serial in serials {
+        let light = lights.get(serial).ok_or(ApiError::UnknownLight(*serial))?.read().await;
+
+        let (Some(power), Some(color)) = (light.power, light.hsbk()) else {
+            return Err(ApiError::InvalidValue(format!("light {} hasn't reported its state yet", serial)));
+        };
+
+        captured.push(SceneLight {
+            serial: *serial,
+            on: power > 0,
+            color,
+        });
+    }
+
+    Ok(captured)
+}
+
+fn check_scene_lights(lights: &[SceneLight]) -> Result<(), ApiError> {
+    if lights.is_empty() {
+        return Err(ApiError::InvalidValue("a scene needs at least one light".to_string()));
+    }
+
+    if let Some(light) = lights.iter().find(|light| !(KELVIN_MIN..=KELVIN_MAX).contains(&light.color.kelvin)) {
+        return Err(ApiError::InvalidValue(format!("kelvin for {} must be between {} and {}", light.serial, KELVIN_MIN, KELVIN_MAX)));
+    }
+
+    Ok(())
+}
+
+pub async fn list_scenes(state: State<AppState>) -> Json<Vec<Scene>> {
+    Json(state.scenes.list().await)
+}
+
+pub async fn get_scene(state: State<AppState>, Path(name): Path<String>) -> Result<Json<Scene>, ApiError> {
+    state.scenes.get(&name).await.map(Json).ok_or(ApiError::UnknownScene(name))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateScene {
+    name: String,
+    // captured as they are right now
+    lights: Vec<Serial>,
+}
+
+pub async fn create_scene(state: State<AppState>, Json(request): Json<CreateScene>) -> Result<Json<Scene>, ApiError> {
+    let name = scene_name(&request.name)?;
+    let lights = capture_lights(&state, &request.lights).await?;
+
+    let scene = Scene::new(name, lights);
+
+    if !state.scenes.insert(scene.clone()).await.map_err(|e| ApiError::Storage(e.to_string()))? {
+        return Err(ApiError::SceneExists(scene.name));
+    }
+
+    log::info!("Saved scene {} with {} lights", scene.name, scene.lights.len());
+
+    Ok(Json(scene))
+}
+
+// renames a scene and replaces its lights, either with the given states or by capturing them again
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneEdit {
+    name: Option<String>,
+    lights: Option<Vec<SceneLight>>,
+    capture: Option<Vec<Serial>>,
+}
+
+pub async fn update_scene(state: State<AppState>, Path(name): Path<String>, Json(edit): Json<SceneEdit>) -> Result<Json<Scene>, ApiError> {
+    let mut scene = state.scenes.get(&name).await.ok_or_else(|| ApiError::UnknownScene(name.clone()))?;
+
+    if let Some(new_name) = &edit.name {
+        scene.name = scene_name(new_name)?;
+    }
+
+    match (edit.lights, edit.capture) {
+        (Some(_), Some(_)) => return Err(ApiError::InvalidValue("give either lights or capture, not both".to_string())),
+        (Some(lights), None) => {
+            check_scene_lights(&lights)?;
+            scene.lights = lights;
+        }
+        (None, Some(serials)) => scene.lights = capture_lights(&state, &serials).await?,
+        (None, None) => {}
+    }
+
+    scene.updated_at_ms = now_ms();
+
+    match state.scenes.update(&name, scene.clone()).await.map_err(|e| ApiError::Storage(e.to_string()))? {
+        None => Err(ApiError::UnknownScene(name)),
+        Some(false) => Err(ApiError::SceneExists(scene.name)),
+        Some(true) => Ok(Json(scene)),
+    }
+}
+
+pub async fn delete_scene(state: State<AppState>, Path(name): Path<String>) -> Result<Json<Scene>, ApiError> {
+    let removed = state.scenes.remove(&name).await.map_err(|e| ApiError::Storage(e.to_string()))?;
+
+    removed.map(Json).ok_or(ApiError::UnknownScene(name))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RecallRequest {
+    duration_ms: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct RecallFailure {
+    serial: Serial,
+    error: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct RecallReport {
+    scene: String,
+    applied: Vec<Serial>,
+    // lights that were unreachable or refused their color, the rest of the scene is still applied
+    failed: Vec<RecallFailure>,
+}
+
+async fn recall_light(state: &AppState, light: SceneLight, duration_ms: Option<u32>) -> Result<(), ApiError> {
+    set_light_color(state, light.serial, ColorChange::from(light.color), duration_ms).await?;
+
+    let address = light_address(state, light.serial).await?;
+
+    set_light_power(state, light.serial, address, light.on, duration_ms).await
+}
+
+// every light is set at once so they fade together, the body is optional
+pub async fn recall_scene(state: State<AppState>, Path(name): Path<String>, OptionalJson(request): OptionalJson<RecallRequest>) -> Result<Json<RecallReport>, ApiError> {
+    let scene = state.scenes.get(&name).await.ok_or(ApiError::UnknownScene(name))?;
+    let duration_ms = request.duration_ms;
+
+    log::debug!("Recalling scene {}", scene.name);
+
+    let mut recalls = JoinSet::new();
+
+    for light in scene.lights {
+        let state = state.0.clone();
+
+        recalls.spawn(async move { (light.serial, recall_light(&state, light, duration_ms).await) });
+    }
+
+    let mut applied = Vec::new();
+    let mut failed = Vec::new();
+
+    while let Some(recall) = recalls.join_next().await {
+        match recall {
+            Ok((serial, Ok(()))) => applied.push(serial),
+            Ok((serial, Err(e))) => failed.push(RecallFailure { serial, error: e.code(), message: e.to_string() }),
+            Err(e) => log::error!("Scene recall task failed: {}", e),
+        }
+    }
+
+    applied.sort_by_key(|serial| serial.0);
+    failed.sort_by_key(|failure| failure.serial.0);
+
+    Ok(Json(RecallReport { scene: scene.name, applied, failed }))
+}
+
+pub async fn openapi_document() -> Json<serde_json::Value> {
+    Json(openapi::document())
+}
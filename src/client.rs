@@ -0,0 +1,167 @@
+use std::{collections::HashMap, net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+
+use lifx_lan::{LifxRequestOptions, Message};
+use tokio::{select, sync::oneshot, time::timeout};
+
+use crate::{ack::Delivery, Request, Serial};
+
+// how long to wait for a device to answer, including retransmissions
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    // the socket task has stopped
+    SocketClosed,
+    // the device never answered, even after retransmitting
+    NoResponse,
+}
+
+// queues requests for the socket task, stamping them with our source id and a sequence number
+#[derive(Clone)]
+pub struct LifxClient {
+    tx: std::sync::mpsc::Sender<Request>,
+
+    source: u32,
+    // counted per light, so replies are matched on (serial, sequence) and a busy network can't wrap
+    // a light's counter while one of its requests is still waiting
+    sequences: Arc<Mutex<HashMap<Serial, u8>>>,
+}
+
+impl LifxClient {
+    pub fn new(tx: std::sync::mpsc::Sender<Request>, source: u32) -> Self {
+        LifxClient {
+            tx,
+            source,
+            sequences: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn source(&self) -> u32 {
+        self.source
+    }
+
+    fn next_sequence(&self, serial: Serial) -> u8 {
+        let mut sequences = self.sequences.lock().unwrap_or_else(|e| e.into_inner());
+
+        // 0 is left to broadcasts and probes
+        let sequence = sequences.entry(serial).or_insert(0);
+        *sequence = sequence.checked_add(1).unwrap_or(1);
+
+        *sequence
+    }
+
+    // broadcasts and probes aren't matched to their replies, so they don't take a sequence number
+    fn options(&self, target: [u8; 8], tagged: bool) -> LifxRequestOptions {
+        let serial = Serial::from_target(&target);
+
+        LifxRequestOptions {
+            tagged,
+            source: self.source,
+            target,
+            ack_required: false,
+            res_required: true,
+            sequence: if tagged { 0 } else { self.next_sequence(serial) },
+        }
+    }
+
+    fn send(&self, request: Request) -> Result<(), RequestError> {
+        self.tx.send(request).map_err(|_| RequestError::SocketClosed)
+    }
+
+    // sends a message to every device reachable at a broadcast address
+    pub fn broadcast(&self, target: String, message: Message) -> Result<(), RequestError> {
+        self.send(Request {
+            options: self.options([0; 8], true),
+            message,
+            target,
+            ack: None,
+            response: None,
+            probe: false,
+        })
+    }
+
+    // sends a message to whatever device might be at an address, without knowing its serial
+    pub fn probe(&self, address: SocketAddr, message: Message) -> Result<(), RequestError> {
+        self.send(Request {
+            options: self.options([0; 8], true),
+            message,
+            target: address.to_string(),
+            ack: None,
+            response: None,
+            probe: true,
+        })
+    }
+
+    // sends a message to a light without waiting for anything back, its reply still updates the registry
+    pub fn send_to_light(&self, serial: Serial, address: SocketAddr, message: Message) -> Result<(), RequestError> {
+        self.send(Request {
+            options: self.options(serial.to_target(), false),
+            message,
+            target: address.to_string(),
+            ack: None,
+            response: None,
+            probe: false,
+        })
+    }
+
+    // sends a change to a light and waits for the device to acknowledge it. no reply is asked for, as the
+    // State a device answers a Set with shows it from before the change
+    pub async fn deliver(&self, serial: Serial, address: SocketAddr, message: Message) -> Result<(), RequestError> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+
+        let mut options = self.options(serial.to_target(), false);
+        options.res_required = false;
+        let sequence = options.sequence;
+
+        self.send(Request {
+            options,
+            message,
+            target: address.to_string(),
+            ack: Some(ack_tx),
+            response: None,
+            probe: false,
+        })?;
+
+        match timeout(RESPONSE_TIMEOUT, ack_rx).await {
+            Ok(Ok(Delivery::Delivered)) => Ok(()),
+            _ => {
+                log::warn!("Light {} did not acknowledge request {}", serial, sequence);
+                Err(RequestError::NoResponse)
+            }
+        }
+    }
+
+    // sends a message to a light and waits for the device's reply to it
+    pub async fn request(&self, serial: Serial, address: SocketAddr, message: Message) -> Result<Message, RequestError> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let (response_tx, response_rx) = oneshot::channel();
+
+        let options = self.options(serial.to_target(), false);
+        let sequence = options.sequence;
+
+        self.send(Request {
+            options,
+            message,
+            target: address.to_string(),
+            ack: Some(ack_tx),
+            response: Some(response_tx),
+            probe: false,
+        })?;
+
+        let response = timeout(RESPONSE_TIMEOUT, async {
+            select! {
+                response = response_rx => response.ok(),
+                // give up early if the retransmissions were never acknowledged
+                Ok(Delivery::Failed) = ack_rx => None,
+            }
+        }).await;
+
+        match response {
+            Ok(Some(response)) => Ok(response),
+            _ => {
+                log::warn!("Light {} did not respond to request {}", serial, sequence);
+                Err(RequestError::NoResponse)
+            }
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// the range most white bulbs support, devices narrower than this are checked against their capabilities
+pub const KELVIN_MIN: u16 = 1500;
+pub const KELVIN_MAX: u16 = 9000;
+
+// the color as LIFX devices take it, every component scaled to the full u16 range apart from kelvin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hsbk {
+    pub hue: u16,
+    pub saturation: u16,
+    pub brightness: u16,
+    pub kelvin: u16,
+}
+
+// what a color string says about the color, anything it leaves out keeps the light's current value.
+//
+// a string is made of space separated parts, later parts overriding earlier ones:
+// - "#ff8800" or "#f80", "rgb:255,136,0"
+// - "hsb:30,100%,40%" with hue in degrees, "hue:30", "saturation:0.5", "brightness:40%"
+// - "kelvin:2700" or "2700K", which also drops the saturation to get white
+// - "xy:0.4,0.4" for CIE 1931 chromaticity
+// - LIFX names (white, red, orange, yellow, cyan, green, blue, purple, pink) which only set the hue
+//   and saturation, or any other CSS color name
+// - a bare percentage sets the brightness, so "2700K at 40%" works
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColorChange {
+    pub hue: Option<u16>,
+    pub saturation: Option<u16>,
+    pub brightness: Option<u16>,
+    pub kelvin: Option<u16>,
+}
+
+impl ColorChange {
+    pub fn is_empty(&self) -> bool {
+        *self == ColorChange::default()
+    }
+
+    pub fn apply(&self, current: Hsbk) -> Hsbk {
+        Hsbk {
+            hue: self.hue.unwrap_or(current.hue),
+            saturation: self.saturation.unwrap_or(current.saturation),
+            brightness: self.brightness.unwrap_or(current.brightness),
+            kelvin: self.kelvin.unwrap_or(current.kelvin),
+        }
+    }
+
+    fn merge(&mut self, other: ColorChange) {
+        self.hue = other.hue.or(self.hue);
+        self.saturation = other.saturation.or(self.saturation);
+        self.brightness = other.brightness.or(self.brightness);
+        self.kelvin = other.kelvin.or(self.kelvin);
+    }
+
+    pub fn from_hsb(hue: f64, saturation: f64, brightness: f64) -> Self {
+        ColorChange {
+            hue: Some(hue_to_u16(hue)),
+            saturation: Some(fraction_to_u16(saturation)),
+            brightness: Some(fraction_to_u16(brightness)),
+            kelvin: None,
+        }
+    }
+
+    // HSV of the gamma encoded values, the same model the LIFX apps and CSS color pickers use, so
+    // "#ff8800" comes out at the 32 degrees a picker shows for it
+    pub fn from_rgb(red: u8, green: u8, blue: u8) -> Self {
+        let (hue, saturation, brightness) = rgb_to_hsv([red, green, blue].map(|c| c as f64 / 255.0));
+
+        ColorChange::from_hsb(hue, saturation, brightness)
+    }
+
+    // chromaticity carries no brightness, so the light's current brightness is kept
+    pub fn from_xy(x: f64, y: f64) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&x) || !(y > 0.0 && y <= 1.0) || x + y > 1.0 {
+            return Err(format!("xy coordinates out of range: {}, {}", x, y));
+        }
+
+        let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+
+        // XYZ to linear sRGB, colors outside the sRGB gamut are clipped to its edge
+        let linear = [
+            3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+        ]
+        .map(|c| c.max(0.0));
+
+        // scaled to full brightness and gamma encoded, so the hue and saturation match from_rgb
+        let max = linear.into_iter().fold(0.0, f64::max);
+        let (hue, saturation, _) = rgb_to_hsv(linear.map(|c| linear_to_srgb(c / max)));
+
+        Ok(ColorChange {
+            hue: Some(hue_to_u16(hue)),
+            saturation: Some(fraction_to_u16(saturation)),
+            brightness: None,
+            kelvin: None,
+        })
+    }
+
+    pub fn from_kelvin(kelvin: u16) -> Result<Self, String> {
+        if !(KELVIN_MIN..=KELVIN_MAX).contains(&kelvin) {
+            return Err(format!("kelvin must be between {} and {}", KELVIN_MIN, KELVIN_MAX));
+        }
+
+        Ok(ColorChange {
+            saturation: Some(0),
+            kelvin: Some(kelvin),
+            ..ColorChange::default()
+        })
+    }
+}
+
+impl From<Hsbk> for ColorChange {
+    fn from(color: Hsbk) -> Self {
+        ColorChange {
+            hue: Some(color.hue),
+            saturation: Some(color.saturation),
+            brightness: Some(color.brightness),
+            kelvin: Some(color.kelvin),
+        }
+    }
+}
+
+impl FromStr for ColorChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut change = ColorChange::default();
+
+        for part in s.split_whitespace() {
+            change.merge(parse_part(&part.to_ascii_lowercase())?);
+        }
+
+        if change.is_empty() {
+            return Err(format!("no color given in {:?}", s));
+        }
+
+        Ok(change)
+    }
+}
+
+impl<'de> Deserialize<'de> for ColorChange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_part(part: &str) -> Result<ColorChange, String> {
+    if part == "at" {
+        return Ok(ColorChange::default());
+    }
+
+    if let Some(hex) = part.strip_prefix('#') {
+        let [red, green, blue] = parse_hex(hex)?;
+        return Ok(ColorChange::from_rgb(red, green, blue));
+    }
+
+    if let Some((key, value)) = part.split_once(':') {
+        return match key {
+            "hue" => Ok(ColorChange { hue: Some(hue_to_u16(parse_degrees(value)?)), ..ColorChange::default() }),
+            "saturation" => Ok(ColorChange { saturation: Some(fraction_to_u16(parse_fraction(value)?)), ..ColorChange::default() }),
+            "brightness" => Ok(ColorChange { brightness: Some(fraction_to_u16(parse_fraction(value)?)), ..ColorChange::default() }),
+            "kelvin" => ColorChange::from_kelvin(parse_number(value)?),
+            "rgb" => {
+                let [red, green, blue] = parse_list::<3>(value)?;
+                Ok(ColorChange::from_rgb(parse_number(red)?, parse_number(green)?, parse_number(blue)?))
+            }
+            "hsb" => {
+                let [hue, saturation, brightness] = parse_list::<3>(value)?;
+                Ok(ColorChange::from_hsb(parse_degrees(hue)?, parse_fraction(saturation)?, parse_fraction(brightness)?))
+            }
+            "xy" => {
+                let [x, y] = parse_list::<2>(value)?;
+                ColorChange::from_xy(parse_number(x)?, parse_number(y)?)
+            }
+            _ => Err(format!("unknown color component: {}", key)),
+        };
+    }
+
+    if let Some(kelvin) = part.strip_suffix('k') {
+        if let Ok(kelvin) = kelvin.parse() {
+            return ColorChange::from_kelvin(kelvin);
+        }
+    }
+
+    if part.ends_with('%') {
+        return Ok(ColorChange { brightness: Some(fraction_to_u16(parse_fraction(part)?)), ..ColorChange::default() });
+    }
+
+    if let Some(hue) = lifx_named_hue(part) {
+        return Ok(ColorChange { hue: Some(hue_to_u16(hue)), saturation: Some(u16::MAX), ..ColorChange::default() });
+    }
+
+    if part == "white" {
+        return Ok(ColorChange { saturation: Some(0), ..ColorChange::default() });
+    }
+
+    if let Some(&(_, rgb)) = CSS_COLORS.iter().find(|(name, _)| *name == part) {
+        return Ok(ColorChange::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+
+    Err(format!("unknown color: {}", part))
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("invalid number: {}", value))
+}
+
+fn parse_list<const N: usize>(value: &str) -> Result<[&str; N], String> {
+    let parts: Vec<&str> = value.split(',').collect();
+
+    parts.try_into().map_err(|_| format!("expected {} comma separated values in {}", N, value))
+}
+
+fn parse_degrees(value: &str) -> Result<f64, String> {
+    let degrees: f64 = parse_number(value)?;
+
+    if !(0.0..=360.0).contains(&degrees) {
+        return Err(format!("hue must be between 0 and 360 degrees: {}", value));
+    }
+
+    Ok(degrees)
+}
+
+// either 0 to 1 or a percentage
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction = match value.strip_suffix('%') {
+        Some(percent) => parse_number::<f64>(percent)? / 100.0,
+        None => parse_number(value)?,
+    };
+
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("value must be between 0 and 1 or 0% and 100%: {}", value));
+    }
+
+    Ok(fraction)
+}
+
+fn parse_hex(hex: &str) -> Result<[u8; 3], String> {
+    let digits: Vec<u8> = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("invalid hex color: #{}", hex))?;
+
+    match digits[..] {
+        [r, g, b] => Ok([r * 17, g * 17, b * 17]),
+        [r1, r2, g1, g2, b1, b2] => Ok([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2]),
+        _ => Err(format!("invalid hex color: #{}", hex)),
+    }
+}
+
+fn hue_to_u16(degrees: f64) -> u16 {
+    ((degrees % 360.0) / 360.0 * 65536.0).round().min(65535.0) as u16
+}
+
+fn fraction_to_u16(fraction: f64) -> u16 {
+    (fraction.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// returns hue in degrees, saturation and value from 0 to 1
+fn rgb_to_hsv([red, green, blue]: [f64; 3]) -> (f64, f64, f64) {
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == red {
+        60.0 * ((green - blue) / delta).rem_euclid(6.0)
+    } else if max == green {
+        60.0 * ((blue - red) / delta + 2.0)
+    } else {
+        60.0 * ((red - green) / delta + 4.0)
+    };
+
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+// the hues the LIFX apps and cloud API use for their color names
+fn lifx_named_hue(name: &str) -> Option<f64> {
+    match name {
+        "red" => Some(0.0),
+        "orange" => Some(36.0),
+        "yellow" => Some(60.0),
+        "green" => Some(120.0),
+        "cyan" => Some(180.0),
+        "blue" => Some(250.0),
+        "purple" => Some(280.0),
+        "pink" => Some(325.0),
+        _ => None,
+    }
+}
+
+const CSS_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("rebeccapurple", 0x663399),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> ColorChange {
+        s.parse().unwrap()
+    }
+
+    fn degrees(hue: Option<u16>) -> f64 {
+        hue.unwrap() as f64 / 65536.0 * 360.0
+    }
+
+    fn fraction(value: Option<u16>) -> f64 {
+        value.unwrap() as f64 / 65535.0
+    }
+
+    #[test]
+    fn hex() {
+        let orange = parse("#ff8800");
+        assert!((degrees(orange.hue) - 32.0).abs() < 0.1);
+        assert_eq!(orange.saturation, Some(u16::MAX));
+        assert_eq!(orange.brightness, Some(u16::MAX));
+        assert_eq!(orange.kelvin, None);
+
+        assert!((fraction(parse("#ff8080").saturation) - 0.5).abs() < 0.01);
+        assert_eq!(parse("#f80"), orange);
+        assert_eq!(parse("#FF8800"), orange);
+    }
+
+    #[test]
+    fn rgb() {
+        assert_eq!(parse("rgb:255,136,0"), parse("#ff8800"));
+
+        let grey = parse("rgb:128,128,128");
+        assert_eq!(grey.saturation, Some(0));
+        assert!((fraction(grey.brightness) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn hsb() {
+        assert_eq!(parse("hsb:30,100%,40%"), ColorChange {
+            hue: Some(5461),
+            saturation: Some(65535),
+            brightness: Some(26214),
+            kelvin: None,
+        });
+        assert_eq!(parse("hsb:30,1,0.4"), parse("hsb:30,100%,40%"));
+        assert_eq!(parse("hue:360").hue, Some(0));
+        assert_eq!(parse("saturation:50%").saturation, Some(32768));
+    }
+
+    #[test]
+    fn kelvin() {
+        let warm = ColorChange { saturation: Some(0), kelvin: Some(2700), ..ColorChange::default() };
+
+        assert_eq!(parse("kelvin:2700"), warm);
+        assert_eq!(parse("2700K"), warm);
+        assert_eq!(parse("2700K at 40%"), ColorChange { brightness: Some(26214), ..warm });
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse("red"), ColorChange { hue: Some(0), saturation: Some(u16::MAX), ..ColorChange::default() });
+        assert_eq!(parse("White"), ColorChange { saturation: Some(0), ..ColorChange::default() });
+        assert_eq!(parse("navy"), parse("#000080"));
+        assert_eq!(parse("blue kelvin:3500").kelvin, Some(3500));
+    }
+
+    #[test]
+    fn xy() {
+        // the sRGB red primary
+        let red = parse("xy:0.64,0.33");
+        assert!(degrees(red.hue) < 1.0);
+        assert!(fraction(red.saturation) > 0.99);
+        assert_eq!(red.brightness, None);
+
+        // D65, sRGB's white point
+        assert!(fraction(parse("xy:0.3127,0.3290").saturation) < 0.01);
+    }
+
+    #[test]
+    fn later_parts_override_earlier_ones() {
+        assert_eq!(parse("#ff8800 hue:120").hue, parse("green").hue);
+    }
+
+    #[test]
+    fn bad_input() {
+        for bad in ["", "  ", "#12345", "#gg0000", "rgb:1,2", "rgb:256,0,0", "hsb:400,1,1", "saturation:2", "kelvin:100",
+            "10000K", "xy:0.8,0.8", "bogus:1", "mauve-ish", "at"]
+        {
+            assert!(bad.parse::<ColorChange>().is_err(), "{:?} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn apply_keeps_what_the_change_leaves_out() {
+        let current = Hsbk { hue: 1, saturation: 2, brightness: 3, kelvin: 3500 };
+
+        assert_eq!(parse("50%").apply(current), Hsbk { brightness: 32768, ..current });
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::{
+    discovery::MAX_SWEEP_HOSTS, interfaces::{InterfaceFilter, InterfaceMatcher, Ipv4Cidr}, scheduler::DEFAULT_MAX_MESSAGES_PER_SECOND,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "lifx.toml";
+
+// how often the config file is checked for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// the port LIFX devices listen on
+pub const LIFX_PORT: u16 = 56700;
+
+// flags override the config file, which overrides the defaults. the environment variables
+// settings used to be read from still work, as fallbacks for their flags
+#[derive(Debug, Clone, Parser)]
+#[command(about = "Control LIFX lights on the local network from a web UI")]
+pub struct Args {
+    /// TOML file to read settings from, reloaded when it changes [default: lifx.toml]
+    #[arg(long, short, env = "LIFX_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address the web UI and API listen on
+    #[arg(long, env = "WEB_LISTEN_ADDRESS")]
+    listen_address: Option<IpAddr>,
+
+    /// Port the web UI and API listen on
+    #[arg(long, env = "WEB_LISTEN_PORT")]
+    port: Option<u16>,
+
+    /// Directory the web UI is served from
+    #[arg(long)]
+    static_dir: Option<PathBuf>,
+
+    /// Local UDP port to talk to lights from, 0 picks a free one
+    #[arg(long, env = "LIFX_BIND_PORT")]
+    bind_port: Option<u16>,
+
+    /// Messages per second sent to each light
+    #[arg(long, env = "LIFX_MAX_MESSAGES_PER_SECOND")]
+    max_messages_per_second: Option<u32>,
+
+    /// Only use these interfaces, by name or CIDR
+    #[arg(long = "interface", env = "LIFX_INTERFACES", value_delimiter = ',')]
+    interfaces: Vec<InterfaceMatcher>,
+
+    /// Never use these interfaces, by name or CIDR
+    #[arg(long = "exclude-interface", env = "LIFX_EXCLUDE_INTERFACES", value_delimiter = ',')]
+    exclude_interfaces: Vec<InterfaceMatcher>,
+
+    /// Networks to keep discovery and polling off
+    #[arg(long = "exclude-network", env = "LIFX_EXCLUDE_NETWORKS", value_delimiter = ',')]
+    exclude_networks: Vec<Ipv4Cidr>,
+
+    /// IPs or CIDR ranges to probe for lights that can't hear broadcasts
+    #[arg(long = "host", env = "LIFX_HOSTS", value_delimiter = ',')]
+    hosts: Vec<Ipv4Cidr>,
+
+    /// Where known lights are saved between runs
+    #[arg(long, env = "LIFX_REGISTRY_PATH")]
+    registry_path: Option<PathBuf>,
+
+    /// Where scenes are saved
+    #[arg(long, env = "LIFX_SCENES_PATH")]
+    scenes_path: Option<PathBuf>,
+}
+
+impl Args {
+    fn config_path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+
+    fn apply(&self, config: &mut Config) {
+        if let Some(listen_address) = self.listen_address {
+            config.web.listen_address = listen_address;
+        }
+        if let Some(port) = self.port {
+            config.web.port = port;
+        }
+        if let Some(static_dir) = &self.static_dir {
+            config.web.static_dir = static_dir.clone();
+        }
+
+        if let Some(bind_port) = self.bind_port {
+            config.lan.bind_port = bind_port;
+        }
+        if let Some(max_messages_per_second) = self.max_messages_per_second {
+            config.lan.max_messages_per_second = max_messages_per_second;
+        }
+        if !self.interfaces.is_empty() {
+            config.lan.interfaces = self.interfaces.clone();
+        }
+        if !self.exclude_interfaces.is_empty() {
+            config.lan.exclude_interfaces = self.exclude_interfaces.clone();
+        }
+        if !self.exclude_networks.is_empty() {
+            config.lan.exclude_networks = self.exclude_networks.clone();
+        }
+        if !self.hosts.is_empty() {
+            config.lan.hosts = self.hosts.clone();
+        }
+
+        if let Some(registry_path) = &self.registry_path {
+            config.storage.registry_path = registry_path.clone();
+        }
+        if let Some(scenes_path) = &self.scenes_path {
+            config.storage.scenes_path = scenes_path.clone();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub web: WebConfig,
+    pub lan: LanConfig,
+    pub discovery: DiscoveryConfig,
+    pub onboarding: OnboardingConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    pub listen_address: IpAddr,
+    pub port: u16,
+    pub static_dir: PathBuf,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            listen_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            static_dir: PathBuf::from("static"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanConfig {
+    // 0 binds an ephemeral port, so we don't collide with other LIFX software on this host
+    pub bind_port: u16,
+    // where devices are sent broadcasts, probes and onboarding requests
+    pub device_port: u16,
+    pub max_messages_per_second: u32,
+
+    // when empty every interface is included
+    pub interfaces: Vec<InterfaceMatcher>,
+    pub exclude_interfaces: Vec<InterfaceMatcher>,
+    pub exclude_networks: Vec<Ipv4Cidr>,
+
+    // bulbs on other VLANs or behind a VPN can't hear our broadcasts, so these are probed directly
+    pub hosts: Vec<Ipv4Cidr>,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        LanConfig {
+            bind_port: 0,
+            device_port: LIFX_PORT,
+            max_messages_per_second: DEFAULT_MAX_MESSAGES_PER_SECOND,
+            interfaces: Vec::new(),
+            exclude_interfaces: Vec::new(),
+            exclude_networks: Vec::new(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+impl LanConfig {
+    pub fn interface_filter(&self) -> InterfaceFilter {
+        InterfaceFilter {
+            include: self.interfaces.clone(),
+            exclude: self.exclude_interfaces.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    // how often known lights are asked for their state
+    pub poll_interval_ms: u64,
+    // how often we broadcast for new devices outside of a scan
+    pub scan_interval_secs: u64,
+
+    pub unreachable_after_missed_polls: u64,
+    pub offline_after_missed_polls: u64,
+    pub evict_after_secs: u64,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            poll_interval_ms: 2000,
+            scan_interval_secs: 60,
+            unreachable_after_missed_polls: 2,
+            offline_after_missed_polls: 5,
+            evict_after_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OnboardingConfig {
+    // a bulb waiting to be onboarded runs its own access point, and is reachable at this address on it
+    pub address: Ipv4Addr,
+}
+
+impl Default for OnboardingConfig {
+    fn default() -> Self {
+        OnboardingConfig {
+            address: Ipv4Addr::new(172, 16, 0, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub registry_path: PathBuf,
+    pub scenes_path: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            registry_path: PathBuf::from("lights.json"),
+            scenes_path: PathBuf::from("scenes.json"),
+        }
+    }
+}
+
+impl Config {
+    // the default config file is allowed to be missing, one given on the command line isn't
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let path = args.config_path();
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && args.config.is_none() => Config::default(),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+
+        args.apply(&mut config);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // every problem at once, so they can all be fixed before the next start
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+
+        if self.web.port == 0 {
+            problems.push("web.port can't be 0".to_string());
+        }
+
+        if !self.web.static_dir.is_dir() {
+            problems.push(format!("web.static_dir {} is not a directory", self.web.static_dir.display()));
+        }
+
+        if self.lan.device_port == 0 {
+            problems.push("lan.device_port can't be 0".to_string());
+        }
+
+        if self.lan.max_messages_per_second == 0 {
+            problems.push("lan.max_messages_per_second must be at least 1".to_string());
+        }
+
+        for cidr in self.lan.hosts.iter().filter(|cidr| cidr.host_count() > MAX_SWEEP_HOSTS) {
+            problems.push(format!("lan.hosts entry {} is too big, ranges are limited to {} hosts", cidr, MAX_SWEEP_HOSTS));
+        }
+
+        if self.discovery.poll_interval_ms == 0 {
+            problems.push("discovery.poll_interval_ms must be at least 1".to_string());
+        }
+
+        if self.discovery.scan_interval_secs == 0 {
+            problems.push("discovery.scan_interval_secs must be at least 1".to_string());
+        }
+
+        // 0 would forget every light as soon as it was found
+        if self.discovery.evict_after_secs == 0 {
+            problems.push("discovery.evict_after_secs must be at least 1".to_string());
+        }
+
+        if self.discovery.offline_after_missed_polls <= self.discovery.unreachable_after_missed_polls {
+            problems.push("discovery.offline_after_missed_polls must be more than discovery.unreachable_after_missed_polls".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    pub fn web_address(&self) -> SocketAddr {
+        SocketAddr::new(self.web.listen_address, self.web.port)
+    }
+
+    pub fn onboarding_address(&self) -> SocketAddr {
+        SocketAddr::from((self.onboarding.address, self.lan.device_port))
+    }
+
+    // a reloaded config with the settings that are only read at startup kept as they're running,
+    // along with the names of the ones that changed
+    fn merge_reload(&self, mut reloaded: Config) -> (Config, Vec<&'static str>) {
+        let mut needs_restart = Vec::new();
+
+        if reloaded.web != self.web {
+            needs_restart.push("web");
+            reloaded.web = self.web.clone();
+        }
+
+        if reloaded.lan.bind_port != self.lan.bind_port {
+            needs_restart.push("lan.bind_port");
+            reloaded.lan.bind_port = self.lan.bind_port;
+        }
+
+        // sockets are bound per interface at startup
+        if reloaded.lan.interfaces != self.lan.interfaces || reloaded.lan.exclude_interfaces != self.lan.exclude_interfaces {
+            needs_restart.push("lan.interfaces");
+            reloaded.lan.interfaces = self.lan.interfaces.clone();
+            reloaded.lan.exclude_interfaces = self.lan.exclude_interfaces.clone();
+        }
+
+        if reloaded.storage != self.storage {
+            needs_restart.push("storage");
+            reloaded.storage = self.storage.clone();
+        }
+
+        (reloaded, needs_restart)
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// reloads the config file whenever it changes, an invalid file is reported and the running config kept
+pub async fn watch_file(args: Args, config: watch::Sender<Config>) {
+    let path = args.config_path();
+    let mut last_modified = modified_at(&path);
+
+    let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let modified = modified_at(&path);
+        if modified == last_modified {
+            continue;
+        }
+
+        last_modified = modified;
+
+        let reloaded = match Config::load(&args) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                log::error!("Keeping the running configuration, {} is invalid:\n{}", path.display(), e);
+                continue;
+            }
+        };
+
+        let (reloaded, needs_restart) = config.borrow().merge_reload(reloaded);
+
+        for setting in needs_restart {
+            log::warn!("{} changed in {}, restart to apply it", setting, path.display());
+        }
+
+        config.send_if_modified(|running| {
+            if *running == reloaded {
+                return false;
+            }
+
+            log::info!("Reloaded configuration from {}", path.display());
+            *running = reloaded;
+
+            true
+        });
+    }
+}
@@ -1,82 +1,440 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::{collections::{HashMap, VecDeque}, net::{IpAddr, SocketAddr, SocketAddrV4}, sync::Arc, time::{Duration, Instant}};
 
-use lifx_lan::{LifxRequestOptions, Message};
-use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+use lifx_lan::Message;
 
-use crate::Request;
+use serde::Serialize;
+use tokio::{select, sync::{mpsc, oneshot, watch}};
 
-const GET_COLOR_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
-const DISCOVERY_REQUEST_INTERVAL_FACTOR: u32 = 5;
+use crate::{
+    client::LifxClient, config::Config, events::{EventBus, LightEvent}, exclusion::NetworkExclusions, interfaces::{select_interfaces, Ipv4Cidr, LanInterface}, now_ms,
+    LightStatus, Lights, Serial, Shutdown,
+};
 
-pub async fn broadcast_discovery_requests(tx: std::sync::mpsc::Sender<Request>, is_terminating: Arc<AtomicBool>) {
-    let mut req_options = LifxRequestOptions {
-        tagged: true,
-        source: 10,
-        target: [0; 8],
-        ack_required: false,
-        res_required: true,
-        sequence: 0,
-    };
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
 
-    let mut count_since_last_discovery = 0;
+// GetService is broadcast often during a burst, and at the configured scan interval once the network has settled
+const BURST_GET_SERVICE_INTERVAL: Duration = Duration::from_secs(1);
+
+const STARTUP_BURST: Duration = Duration::from_secs(10);
+const NETWORK_CHANGE_BURST: Duration = Duration::from_secs(10);
+const REQUESTED_BURST: Duration = Duration::from_secs(3);
+// how long to wait before retrying a handshake a device didn't complete
+const HANDSHAKE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// larger ranges than a /22 would take too long to sweep every discovery pass
+pub const MAX_SWEEP_HOSTS: u64 = 1022;
+// configured hosts are probed a batch per tick, so a whole range doesn't flood the network at once
+const PROBES_PER_TICK: usize = 64;
+
+// how many missed polls it takes for a device to be considered unreachable, then offline, and when it's forgotten
+struct Liveness {
+    // how often devices that finished their handshake are asked for their current state
+    poll_interval: Duration,
+    unreachable_after_missed_polls: u64,
+    offline_after_missed_polls: u64,
+    evict_after: Duration,
+}
+
+impl Liveness {
+    fn status(&self, silent_for: Duration) -> LightStatus {
+        let missed_polls = (silent_for.as_millis() / self.poll_interval.as_millis()) as u64;
+
+        if missed_polls >= self.offline_after_missed_polls {
+            LightStatus::Offline
+        } else if missed_polls >= self.unreachable_after_missed_polls {
+            LightStatus::Unreachable
+        } else {
+            LightStatus::Online
+        }
+    }
+}
+
+// the settings discovery reads from the config, rebuilt whenever the config file changes
+struct Settings {
+    liveness: Liveness,
+    idle_get_service_interval: Duration,
+    static_hosts: Vec<Ipv4Cidr>,
+    device_port: u16,
+}
+
+impl Settings {
+    fn new(config: &Config) -> Self {
+        Settings {
+            liveness: Liveness {
+                poll_interval: Duration::from_millis(config.discovery.poll_interval_ms),
+                unreachable_after_missed_polls: config.discovery.unreachable_after_missed_polls,
+                offline_after_missed_polls: config.discovery.offline_after_missed_polls,
+                evict_after: Duration::from_secs(config.discovery.evict_after_secs),
+            },
+            idle_get_service_interval: Duration::from_secs(config.discovery.scan_interval_secs),
+            static_hosts: config.lan.hosts.clone(),
+            device_port: config.lan.device_port,
+        }
+    }
+}
+
+// what changed over the course of a requested scan
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    // devices we didn't know about before the scan
+    pub found: Vec<Serial>,
+    // known devices that replied during the scan
+    pub refreshed: Vec<Serial>,
+    // known devices that stayed silent
+    pub missing: Vec<Serial>,
+}
+
+// lets other tasks ask discovery for a burst scan
+#[derive(Clone)]
+pub struct DiscoveryHandle {
+    tx: mpsc::UnboundedSender<oneshot::Sender<ScanReport>>,
+}
+
+impl DiscoveryHandle {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<oneshot::Sender<ScanReport>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        (DiscoveryHandle { tx }, rx)
+    }
+
+    // runs a burst scan and waits for it to finish, None if discovery has stopped
+    pub async fn scan(&self) -> Option<ScanReport> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        self.tx.send(reply_tx).ok()?;
+
+        reply_rx.await.ok()
+    }
+}
+
+struct PendingScan {
+    // every light known when the scan started, with when it was last seen
+    known_before: HashMap<Serial, Option<u64>>,
+    started_ms: u64,
+    ends_at: Instant,
+
+    reply: oneshot::Sender<ScanReport>,
+}
+
+enum Stage {
+    // waiting on the replies to the handshake requests
+    Handshaking,
+    // the device is known, only its state needs refreshing
+    Ready { next_poll_at: Instant },
+    Failed { retry_at: Instant },
+}
+
+pub async fn run_discovery(client: LifxClient, lights: Lights, events: EventBus, is_terminating: Arc<Shutdown>, mut config: watch::Receiver<Config>, mut scan_requests: mpsc::UnboundedReceiver<oneshot::Sender<ScanReport>>, interfaces_tx: watch::Sender<Vec<LanInterface>>) {
+    let (handshake_tx, mut handshake_rx) = mpsc::unbounded_channel::<(Serial, bool)>();
+
+    let mut devices: HashMap<Serial, Stage> = HashMap::new();
+    let mut next_get_service_at = Instant::now();
+
+    let mut burst_until = Instant::now() + STARTUP_BURST;
+    let mut previous_interfaces = Vec::new();
+    let mut scans: Vec<PendingScan> = Vec::new();
+    let mut pending_probes = VecDeque::new();
+
+    let started_ms = now_ms();
+
+    // the socket was bound with the filter from startup, so it isn't reloaded
+    let interface_filter = config.borrow().lan.interface_filter();
+
+    let mut settings = Settings::new(&config.borrow_and_update());
+    let mut exclusions = NetworkExclusions::new(&config.borrow());
 
     loop {
-        if is_terminating.load(std::sync::atomic::Ordering::Relaxed) {
+        if is_terminating.is_triggered() {
             break;
         }
-        
-        let network_interfaces = NetworkInterface::show().unwrap();
-
-        for interface in &network_interfaces {
-            for addr in &interface.addr {
-                match addr {
-                    Addr::V4(v4addr) => {
-                        if let Some(broadcast_address) = v4addr.broadcast {
-                            if broadcast_address.to_string() == "172.16.0.255" {
-                                // lifx bulb's own network, skip
-                                continue;
-                            }
-
-                            let target = format!("{}:56700", broadcast_address);
-                            // if count_since_last_discovery == DISCOVERY_REQUEST_INTERVAL_FACTOR {
-                            //     tx.send(Request {
-                            //         options: req_options.clone(),
-                            //         message: Message::GetColor,
-                            //         target: target.clone(),
-                            //     })
-                            //     .unwrap();
-                        
-                        
-                            //     // tx.send(Request {
-                            //     //     options: req_options.clone(),
-                            //     //     message: Message::GetHostFirmware,
-                            //     //     target: target.clone(),
-                            //     // })
-                            //     // .unwrap();
-
-                            //     // req_options.increment_sequence();
-                            // }
-
-                            tx.send(Request {
-                                options: req_options.clone(),
-                                message: Message::GetColor,
-                                target: target.clone(),
-                            })
-                            .unwrap();
-                            req_options.increment_sequence();
-                        }
+
+        if config.has_changed().unwrap_or(false) {
+            let config = config.borrow_and_update();
+
+            settings = Settings::new(&config);
+            exclusions.update(&config);
+        }
+
+        let now = Instant::now();
+
+        let interfaces = select_interfaces(&interface_filter);
+        exclusions.refresh(&interfaces).await;
+
+        if interfaces != previous_interfaces {
+            if !previous_interfaces.is_empty() {
+                log::info!("Network interfaces changed, scanning for devices");
+            }
+
+            burst_until = burst_until.max(now + NETWORK_CHANGE_BURST);
+            next_get_service_at = now;
+            previous_interfaces = interfaces.clone();
+            interfaces_tx.send_replace(interfaces.clone());
+        }
+
+        if now >= next_get_service_at {
+            broadcast_get_service(&client, &interfaces, &exclusions, settings.device_port);
+            queue_static_host_probes(&mut pending_probes, &settings.static_hosts, &exclusions, settings.device_port);
+
+            let interval = if now < burst_until { BURST_GET_SERVICE_INTERVAL } else { settings.idle_get_service_interval };
+            next_get_service_at = now + interval;
+        }
+
+        send_probes(&client, &mut pending_probes);
+
+        finish_scans(&lights, &mut scans, now).await;
+
+        update_liveness(&lights, &events, &mut devices, &settings.liveness, started_ms).await;
+
+        for (serial, address) in known_lights(&lights).await {
+            let Some(address) = address else {
+                continue;
+            };
+
+            if let IpAddr::V4(ip) = address.ip() {
+                if exclusions.excludes_address(ip) {
+                    continue;
+                }
+            }
+
+            match devices.get(&serial) {
+                None => {
+                    log::info!("Starting handshake with {} at {}", serial, address);
+                    start_handshake(&client, &handshake_tx, serial, address);
+                    devices.insert(serial, Stage::Handshaking);
+                }
+                Some(Stage::Failed { retry_at }) if now >= *retry_at => {
+                    log::info!("Retrying handshake with {} at {}", serial, address);
+                    start_handshake(&client, &handshake_tx, serial, address);
+                    devices.insert(serial, Stage::Handshaking);
+                }
+                Some(Stage::Ready { next_poll_at }) if now >= *next_poll_at => {
+                    if client.send_to_light(serial, address, Message::GetColor).is_err() {
+                        return;
                     }
-                    _ => {}
+
+                    devices.insert(serial, Stage::Ready { next_poll_at: now + settings.liveness.poll_interval });
                 }
+                _ => {}
             }
         }
 
-        if count_since_last_discovery == DISCOVERY_REQUEST_INTERVAL_FACTOR {
-            count_since_last_discovery = 0;
-        } else {
-            count_since_last_discovery += 1;
+        select! {
+            _ = is_terminating.wait() => break,
+            Some(reply) = scan_requests.recv() => {
+                log::info!("Scan requested");
+
+                let now = Instant::now();
+
+                scans.push(PendingScan {
+                    known_before: last_seen_by_light(&lights).await,
+                    started_ms: now_ms(),
+                    ends_at: now + REQUESTED_BURST,
+                    reply,
+                });
+
+                burst_until = burst_until.max(now + REQUESTED_BURST);
+                next_get_service_at = now;
+
+                // ask every known device for its state straight away, so the report reflects who answers
+                for stage in devices.values_mut() {
+                    if let Stage::Ready { next_poll_at } = stage {
+                        *next_poll_at = now;
+                    }
+                }
+            }
+            Some((serial, complete)) = handshake_rx.recv() => {
+                if complete {
+                    log::info!("Handshake with {} complete", serial);
+                    devices.insert(serial, Stage::Ready { next_poll_at: Instant::now() + settings.liveness.poll_interval });
+                } else {
+                    log::warn!("Handshake with {} incomplete, retrying in {:?}", serial, HANDSHAKE_RETRY_INTERVAL);
+                    devices.insert(serial, Stage::Failed { retry_at: Instant::now() + HANDSHAKE_RETRY_INTERVAL });
+                }
+            }
+            _ = tokio::time::sleep(TICK_INTERVAL) => {}
         }
+    }
+}
+
+fn broadcast_get_service(client: &LifxClient, interfaces: &[LanInterface], exclusions: &NetworkExclusions, device_port: u16) {
+    for interface in interfaces {
+        if exclusions.excludes_interface(interface) {
+            continue;
+        }
+
+        let target = format!("{}:{}", interface.broadcast, device_port);
+
+        if let Err(e) = client.broadcast(target, Message::GetService) {
+            log::error!("Failed to queue discovery broadcast: {:?}", e);
+        }
+    }
+}
+
+// each configured host is probed once a pass, a pass that starts before the last one was sent out is skipped
+fn queue_static_host_probes(pending_probes: &mut VecDeque<SocketAddr>, static_hosts: &[Ipv4Cidr], exclusions: &NetworkExclusions, device_port: u16) {
+    if !pending_probes.is_empty() {
+        log::debug!("Still probing {} configured hosts, skipping this pass", pending_probes.len());
+        return;
+    }
+
+    for cidr in static_hosts {
+        for host in cidr.hosts().filter(|host| !exclusions.excludes_address(*host)) {
+            pending_probes.push_back(SocketAddr::V4(SocketAddrV4::new(host, device_port)));
+        }
+    }
+}
+
+// a device that answers is handshaken like one found by broadcast, so GetService is all it's asked
+fn send_probes(client: &LifxClient, pending_probes: &mut VecDeque<SocketAddr>) {
+    let batch = pending_probes.len().min(PROBES_PER_TICK);
+
+    for address in pending_probes.drain(..batch) {
+        if let Err(e) = client.probe(address, Message::GetService) {
+            log::error!("Failed to queue probe for {}: {:?}", address, e);
+            return;
+        }
+    }
+}
 
-        tokio::time::sleep(GET_COLOR_INTERVAL).await;
+async fn last_seen_by_light(lights: &Lights) -> HashMap<Serial, Option<u64>> {
+    let lights = lights.read().await;
+
+    let mut last_seen = HashMap::with_capacity(lights.len());
+    for (serial, light) in lights.iter() {
+        last_seen.insert(*serial, light.read().await.last_seen_ms);
+    }
+
+    last_seen
+}
+
+// replies to every requested scan whose burst is over
+async fn finish_scans(lights: &Lights, scans: &mut Vec<PendingScan>, now: Instant) {
+    if !scans.iter().any(|scan| now >= scan.ends_at) {
+        return;
     }
-}
\ No newline at end of file
+
+    let last_seen = last_seen_by_light(lights).await;
+
+    let (finished, pending): (Vec<_>, Vec<_>) = scans.drain(..).partition(|scan| now >= scan.ends_at);
+    *scans = pending;
+
+    for scan in finished {
+        let mut report = ScanReport::default();
+
+        for (serial, last_seen_ms) in &last_seen {
+            let seen_during_scan = last_seen_ms.is_some_and(|last_seen_ms| last_seen_ms >= scan.started_ms);
+
+            if !scan.known_before.contains_key(serial) {
+                report.found.push(*serial);
+            } else if seen_during_scan {
+                report.refreshed.push(*serial);
+            }
+        }
+
+        for serial in scan.known_before.keys() {
+            let seen_during_scan = last_seen.get(serial).copied().flatten().is_some_and(|last_seen_ms| last_seen_ms >= scan.started_ms);
+
+            if !seen_during_scan {
+                report.missing.push(*serial);
+            }
+        }
+
+        log::info!("Scan finished: {} found, {} refreshed, {} missing", report.found.len(), report.refreshed.len(), report.missing.len());
+
+        let _ = scan.reply.send(report);
+    }
+}
+
+// refreshes each light's status from how long it's been silent, and forgets the ones gone for too long.
+// stale lights keep their status until they reply, and are only forgotten once silent that long since startup
+async fn update_liveness(lights: &Lights, events: &EventBus, devices: &mut HashMap<Serial, Stage>, liveness: &Liveness, started_ms: u64) {
+    let now = now_ms();
+    let mut evicted = Vec::new();
+
+    {
+        let lights = lights.read().await;
+
+        for (serial, light) in lights.iter() {
+            let mut light = light.write().await;
+
+            let Some(last_seen_ms) = light.last_seen_ms else {
+                continue;
+            };
+
+            let is_stale = light.status == LightStatus::Stale;
+            let silent_since = if is_stale { last_seen_ms.max(started_ms) } else { last_seen_ms };
+            let silent_for = Duration::from_millis(now.saturating_sub(silent_since));
+
+            if silent_for >= liveness.evict_after {
+                evicted.push(*serial);
+                continue;
+            }
+
+            if is_stale {
+                continue;
+            }
+
+            let status = liveness.status(silent_for);
+            if status != light.status {
+                log::info!("Light {} is now {:?} (last seen {:?} ago)", serial, status, silent_for);
+                light.status = status;
+
+                let light = light.clone();
+                events.publish(match status {
+                    LightStatus::Offline => LightEvent::Offline { light },
+                    _ => LightEvent::Changed { light },
+                });
+            }
+        }
+    }
+
+    if evicted.is_empty() {
+        return;
+    }
+
+    let mut lights = lights.write().await;
+
+    for serial in evicted {
+        log::info!("Forgetting light {}, not seen for over {:?}", serial, liveness.evict_after);
+
+        lights.remove(&serial);
+        devices.remove(&serial);
+
+        events.publish(LightEvent::Removed { serial });
+    }
+}
+
+async fn known_lights(lights: &Lights) -> Vec<(Serial, Option<SocketAddr>)> {
+    let lights = lights.read().await;
+
+    let mut known = Vec::with_capacity(lights.len());
+    for (serial, light) in lights.iter() {
+        known.push((*serial, light.read().await.address));
+    }
+
+    known
+}
+
+fn start_handshake(client: &LifxClient, results: &mpsc::UnboundedSender<(Serial, bool)>, serial: Serial, address: SocketAddr) {
+    let client = client.clone();
+    let results = results.clone();
+
+    tokio::spawn(async move {
+        let complete = handshake(&client, serial, address).await;
+        let _ = results.send((serial, complete));
+    });
+}
+
+// asks a newly found device about everything we keep track of, the socket stores each reply as it arrives
+async fn handshake(client: &LifxClient, serial: Serial, address: SocketAddr) -> bool {
+    let (version, firmware, label, group, location, color) = tokio::join!(
+        client.request(serial, address, Message::GetVersion),
+        client.request(serial, address, Message::GetHostFirmware),
+        client.request(serial, address, Message::GetLabel),
+        client.request(serial, address, Message::GetGroup),
+        client.request(serial, address, Message::GetLocation),
+        client.request(serial, address, Message::GetColor),
+    );
+
+    version.is_ok() && firmware.is_ok() && label.is_ok() && group.is_ok() && location.is_ok() && color.is_ok()
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{client::RequestError, groups::CollectionId, Serial};
+
+// everything a route can fail with, sent back as a JSON body with a matching status code
+#[derive(Debug)]
+pub enum ApiError {
+    UnknownLight(Serial),
+    UnknownCollection(CollectionId),
+    // a selector that didn't pick any light
+    NoLightsMatch(String),
+    UnknownScene(String),
+    SceneExists(String),
+    // a label that doesn't fit the 32 byte field devices store it in
+    InvalidLabel(String),
+    InvalidValue(String),
+    // the light can't do what was asked of it, like showing a color on a white-only bulb
+    Unsupported { serial: Serial, reason: String },
+    // the socket task is gone, nothing can reach the lights until a restart
+    SocketUnavailable,
+    DiscoveryUnavailable,
+    LightUnreachable(Serial),
+    UnexpectedResponse(Serial),
+    OnboardingFailed(String),
+    // reading or writing something we keep on disk failed
+    Storage(String),
+    // a body, query string or path that doesn't parse
+    BadRequest(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    // maps a failed request to a light onto the error the caller should see
+    pub fn from_request(serial: Serial, e: RequestError) -> Self {
+        match e {
+            RequestError::SocketClosed => ApiError::SocketUnavailable,
+            RequestError::NoResponse => ApiError::LightUnreachable(serial),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::UnknownLight(_) | ApiError::UnknownCollection(_) | ApiError::NoLightsMatch(_) | ApiError::UnknownScene(_) => StatusCode::NOT_FOUND,
+            ApiError::SceneExists(_) => StatusCode::CONFLICT,
+            ApiError::InvalidLabel(_) | ApiError::InvalidValue(_) | ApiError::Unsupported { .. } | ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::SocketUnavailable | ApiError::DiscoveryUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::LightUnreachable(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::UnexpectedResponse(_) | ApiError::OnboardingFailed(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::UnknownLight(_) => "unknown_light",
+            ApiError::UnknownCollection(_) => "unknown_collection",
+            ApiError::NoLightsMatch(_) => "no_lights_match",
+            ApiError::UnknownScene(_) => "unknown_scene",
+            ApiError::SceneExists(_) => "scene_exists",
+            ApiError::InvalidLabel(_) => "invalid_label",
+            ApiError::InvalidValue(_) => "invalid_value",
+            ApiError::Unsupported { .. } => "unsupported",
+            ApiError::SocketUnavailable => "socket_unavailable",
+            ApiError::DiscoveryUnavailable => "discovery_unavailable",
+            ApiError::LightUnreachable(_) => "light_unreachable",
+            ApiError::UnexpectedResponse(_) => "unexpected_response",
+            ApiError::OnboardingFailed(_) => "onboarding_failed",
+            ApiError::Storage(_) => "storage",
+            ApiError::BadRequest(_) => "bad_request",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::UnknownLight(serial) => write!(f, "no light with serial {}", serial),
+            ApiError::UnknownCollection(id) => write!(f, "no group or location with id {}", id),
+            ApiError::NoLightsMatch(selector) => write!(f, "could not find light with selector: {}", selector),
+            ApiError::UnknownScene(name) => write!(f, "no scene named {}", name),
+            ApiError::SceneExists(name) => write!(f, "a scene named {} already exists", name),
+            ApiError::InvalidLabel(reason) => write!(f, "invalid label: {}", reason),
+            ApiError::InvalidValue(reason) => write!(f, "invalid value: {}", reason),
+            ApiError::Unsupported { serial, reason } => write!(f, "light {} can't do that: {}", serial, reason),
+            ApiError::SocketUnavailable => write!(f, "the LIFX socket is not running"),
+            ApiError::DiscoveryUnavailable => write!(f, "discovery is not running"),
+            ApiError::LightUnreachable(serial) => write!(f, "light {} did not respond", serial),
+            ApiError::UnexpectedResponse(serial) => write!(f, "light {} sent an unexpected response", serial),
+            ApiError::OnboardingFailed(reason) => write!(f, "onboarding failed: {}", reason),
+            ApiError::Storage(reason) => write!(f, "storage error: {}", reason),
+            ApiError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        };
+
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
@@ -0,0 +1,43 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{Light, Serial};
+
+// how far a slow subscriber can fall behind before it starts missing events
+const EVENT_CAPACITY: usize = 256;
+
+// changes to the light registry, pushed to WebSocket clients as they happen
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LightEvent {
+    // a device we didn't know about replied
+    Found { light: Light },
+    // the cached state of a known light changed
+    Changed { light: Light },
+    // missed enough polls that it's probably switched off at the wall
+    Offline { light: Light },
+    // forgotten after being gone for too long
+    Removed { serial: Serial },
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<LightEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CAPACITY);
+
+        EventBus { tx }
+    }
+
+    // events published while nobody is subscribed are dropped
+    pub fn publish(&self, event: LightEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LightEvent> {
+        self.tx.subscribe()
+    }
+}
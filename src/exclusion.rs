@@ -0,0 +1,100 @@
+use std::{collections::HashMap, net::{Ipv4Addr, SocketAddr, SocketAddrV4}, time::{Duration, Instant}};
+
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{config::Config, interfaces::{Ipv4Cidr, LanInterface}};
+
+const SETUP_AP_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const SETUP_AP_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct SetupApCheck {
+    is_setup_ap: bool,
+    checked_at: Instant,
+}
+
+// networks discovery and polling should stay off, either configured or detected as a bulb's setup access point.
+// onboarding talks to the bulb over TCP directly, so it isn't affected.
+pub struct NetworkExclusions {
+    configured: Vec<Ipv4Cidr>,
+
+    // where a bulb waiting to be onboarded answers on its own access point
+    setup_ap: SocketAddrV4,
+
+    // keyed by our address on the interface
+    setup_ap_checks: HashMap<Ipv4Addr, SetupApCheck>,
+    setup_ap_interfaces: Vec<LanInterface>,
+}
+
+impl NetworkExclusions {
+    pub fn new(config: &Config) -> Self {
+        NetworkExclusions {
+            configured: config.lan.exclude_networks.clone(),
+            setup_ap: SocketAddrV4::new(config.onboarding.address, config.lan.device_port),
+            setup_ap_checks: HashMap::new(),
+            setup_ap_interfaces: Vec::new(),
+        }
+    }
+
+    // a new setup access point address means every interface has to be checked again
+    pub fn update(&mut self, config: &Config) {
+        self.configured = config.lan.exclude_networks.clone();
+
+        let setup_ap = SocketAddrV4::new(config.onboarding.address, config.lan.device_port);
+        if setup_ap != self.setup_ap {
+            self.setup_ap = setup_ap;
+            self.setup_ap_checks.clear();
+            self.setup_ap_interfaces.clear();
+        }
+    }
+
+    // checks whether any of the interfaces that could be on a setup access point actually are
+    pub async fn refresh(&mut self, interfaces: &[LanInterface]) {
+        let now = Instant::now();
+
+        for interface in interfaces {
+            let gateway = *self.setup_ap.ip();
+            if !interface.contains(gateway) || interface.address == gateway {
+                continue;
+            }
+
+            if let Some(check) = self.setup_ap_checks.get(&interface.address) {
+                if now < check.checked_at + SETUP_AP_CHECK_INTERVAL {
+                    continue;
+                }
+            }
+
+            let is_setup_ap = answers_on_lifx_port(self.setup_ap).await;
+
+            let was_setup_ap = self.setup_ap_checks.get(&interface.address).is_some_and(|check| check.is_setup_ap);
+            if is_setup_ap != was_setup_ap {
+                if is_setup_ap {
+                    log::info!("{} is connected to a LIFX setup network, excluding it from discovery", interface.name);
+                } else {
+                    log::info!("{} is no longer connected to a LIFX setup network", interface.name);
+                }
+            }
+
+            self.setup_ap_checks.insert(interface.address, SetupApCheck { is_setup_ap, checked_at: now });
+        }
+
+        self.setup_ap_interfaces = interfaces
+            .iter()
+            .filter(|interface| self.setup_ap_checks.get(&interface.address).is_some_and(|check| check.is_setup_ap))
+            .cloned()
+            .collect();
+    }
+
+    pub fn excludes_interface(&self, interface: &LanInterface) -> bool {
+        self.configured.iter().any(|cidr| cidr.contains(interface.address))
+            || self.setup_ap_interfaces.iter().any(|setup| setup.address == interface.address)
+    }
+
+    pub fn excludes_address(&self, ip: Ipv4Addr) -> bool {
+        self.configured.iter().any(|cidr| cidr.contains(ip))
+            || self.setup_ap_interfaces.iter().any(|setup| setup.contains(ip))
+    }
+}
+
+async fn answers_on_lifx_port(address: SocketAddrV4) -> bool {
+    matches!(timeout(SETUP_AP_CONNECT_TIMEOUT, TcpStream::connect(SocketAddr::V4(address))).await, Ok(Ok(_)))
+}
@@ -0,0 +1,197 @@
+use std::{collections::HashMap, fmt, net::SocketAddr, str::FromStr};
+
+use lifx_lan::Message;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{now_ms, packet::pad_label, Light, LightStatus, Lights, Serial};
+
+// the 16 byte id the LIFX app gives each group and location, shown in UUID form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CollectionId(pub [u8; 16]);
+
+impl CollectionId {
+    // a random version 4 UUID
+    pub fn generate() -> Self {
+        let mut id: [u8; 16] = rand::thread_rng().gen();
+
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+
+        CollectionId(id)
+    }
+}
+
+impl fmt::Display for CollectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for CollectionId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.chars().filter(|c| *c != '-').collect();
+
+        if hex.len() != 32 || !hex.is_ascii() {
+            return Err(format!("invalid id: {}", s));
+        }
+
+        let mut id = [0u8; 16];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid id: {}", s))?;
+        }
+
+        Ok(CollectionId(id))
+    }
+}
+
+impl Serialize for CollectionId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CollectionId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// a light's group or location as stored on the device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    pub id: CollectionId,
+    pub label: String,
+    // nanoseconds since the epoch, when lights disagree on a label the most recent one wins
+    pub updated_at: u64,
+}
+
+impl Membership {
+    // a membership that will win over every copy already on the lights
+    pub fn new(id: CollectionId, label: String) -> Self {
+        Membership {
+            id,
+            label,
+            updated_at: now_ms() * 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionKind {
+    Group,
+    Location,
+}
+
+impl CollectionKind {
+    pub fn membership<'a>(&self, light: &'a Light) -> Option<&'a Membership> {
+        match self {
+            CollectionKind::Group => light.group.as_ref(),
+            CollectionKind::Location => light.location.as_ref(),
+        }
+    }
+
+    // None when the label doesn't fit in the device's label field
+    pub fn set_message(&self, membership: &Membership) -> Option<Message> {
+        let label = pad_label(&membership.label)?;
+
+        Some(match self {
+            CollectionKind::Group => Message::SetGroup {
+                group: membership.id.0,
+                label,
+                updated_at: membership.updated_at,
+            },
+            CollectionKind::Location => Message::SetLocation {
+                location: membership.id.0,
+                label,
+                updated_at: membership.updated_at,
+            },
+        })
+    }
+}
+
+// a group or location, gathered from the lights that are in it
+#[derive(Debug, Clone, Serialize)]
+pub struct Collection {
+    pub id: CollectionId,
+    pub label: String,
+    pub updated_at: u64,
+
+    pub lights: Vec<Serial>,
+}
+
+pub async fn collections(lights: &Lights, kind: CollectionKind) -> Vec<Collection> {
+    let lights = lights.read().await;
+
+    let mut collections: HashMap<CollectionId, Collection> = HashMap::new();
+
+    for light in lights.values() {
+        let light = light.read().await;
+
+        let Some(membership) = kind.membership(&light) else {
+            continue;
+        };
+
+        let collection = collections.entry(membership.id).or_insert_with(|| Collection {
+            id: membership.id,
+            label: membership.label.clone(),
+            updated_at: membership.updated_at,
+            lights: Vec::new(),
+        });
+
+        if membership.updated_at > collection.updated_at {
+            collection.label = membership.label.clone();
+            collection.updated_at = membership.updated_at;
+        }
+
+        collection.lights.push(light.serial);
+    }
+
+    let mut collections: Vec<Collection> = collections.into_values().collect();
+    collections.sort_by(|a, b| a.label.cmp(&b.label));
+
+    collections
+}
+
+pub async fn collection(lights: &Lights, kind: CollectionKind, id: CollectionId) -> Option<Collection> {
+    collections(lights, kind).await.into_iter().find(|collection| collection.id == id)
+}
+
+// a light in a group or location, with where to reach it
+#[derive(Debug, Clone, Copy)]
+pub struct Member {
+    pub serial: Serial,
+    pub address: SocketAddr,
+    pub status: LightStatus,
+}
+
+// the lights in a group or location we know an address for
+pub async fn members(lights: &Lights, kind: CollectionKind, id: CollectionId) -> Vec<Member> {
+    let lights = lights.read().await;
+
+    let mut members = Vec::new();
+
+    for light in lights.values() {
+        let light = light.read().await;
+
+        if kind.membership(&light).is_some_and(|membership| membership.id == id) {
+            if let Some(address) = light.address {
+                members.push(Member { serial: light.serial, address, status: light.status });
+            }
+        }
+    }
+
+    members
+}
@@ -0,0 +1,181 @@
+use std::{fmt, net::Ipv4Addr, str::FromStr};
+
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+use serde::{Deserialize, Deserializer};
+
+// an IPv4 network interface we can broadcast on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanInterface {
+    pub name: String,
+
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub broadcast: Ipv4Addr,
+}
+
+impl LanInterface {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+
+        u32::from(self.address) & mask == u32::from(ip) & mask || ip == self.broadcast
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub network: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix as u32) };
+
+        u32::from(self.network) & mask == u32::from(ip) & mask
+    }
+
+    pub fn host_count(&self) -> u64 {
+        match self.prefix {
+            32 => 1,
+            31 => 2,
+            prefix => (1u64 << (32 - prefix as u32)) - 2,
+        }
+    }
+
+    // every usable host address, leaving out the network and broadcast addresses where a subnet has them
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix as u32) };
+        let first = u32::from(self.network) & mask;
+        let last = first | !mask;
+
+        let (first, last) = if self.prefix >= 31 { (first, last) } else { (first + 1, last - 1) };
+
+        (first..=last).map(Ipv4Addr::from)
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, prefix.parse::<u8>().map_err(|_| format!("invalid prefix length in {}", s))?),
+            None => (s, 32),
+        };
+
+        if prefix > 32 {
+            return Err(format!("invalid prefix length in {}", s));
+        }
+
+        let network = network.parse::<Ipv4Addr>().map_err(|_| format!("invalid network address in {}", s))?;
+
+        Ok(Ipv4Cidr { network, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv4Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// matches an interface either by name or by a network its address falls in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceMatcher {
+    Name(String),
+    Cidr(Ipv4Cidr),
+}
+
+impl InterfaceMatcher {
+    pub fn matches(&self, interface: &LanInterface) -> bool {
+        match self {
+            InterfaceMatcher::Name(name) => &interface.name == name,
+            InterfaceMatcher::Cidr(cidr) => cidr.contains(interface.address),
+        }
+    }
+}
+
+impl FromStr for InterfaceMatcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+            Ok(InterfaceMatcher::Cidr(s.parse()?))
+        } else if s.is_empty() {
+            Err("empty interface name".to_string())
+        } else {
+            Ok(InterfaceMatcher::Name(s.to_string()))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for InterfaceMatcher {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceFilter {
+    // when empty every interface is included
+    pub include: Vec<InterfaceMatcher>,
+    pub exclude: Vec<InterfaceMatcher>,
+}
+
+impl InterfaceFilter {
+    pub fn allows(&self, interface: &LanInterface) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|matcher| matcher.matches(interface));
+
+        included && !self.exclude.iter().any(|matcher| matcher.matches(interface))
+    }
+}
+
+// lists the broadcast-capable IPv4 interfaces allowed by the filter
+pub fn select_interfaces(filter: &InterfaceFilter) -> Vec<LanInterface> {
+    let network_interfaces = match NetworkInterface::show() {
+        Ok(network_interfaces) => network_interfaces,
+        Err(e) => {
+            log::error!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut interfaces = Vec::new();
+
+    for interface in &network_interfaces {
+        for addr in &interface.addr {
+            if let Addr::V4(v4addr) = addr {
+                let (Some(broadcast), Some(netmask)) = (v4addr.broadcast, v4addr.netmask) else {
+                    continue;
+                };
+
+                if v4addr.ip.is_loopback() {
+                    continue;
+                }
+
+                let interface = LanInterface {
+                    name: interface.name.clone(),
+                    address: v4addr.ip,
+                    netmask,
+                    broadcast,
+                };
+
+                if filter.allows(&interface) {
+                    interfaces.push(interface);
+                }
+            }
+        }
+    }
+
+    interfaces
+}
@@ -0,0 +1,337 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, routing::{get, post, put}, Router};
+use lifx_lan::Message;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::{
+    color::ColorChange, error::ApiError, extract::{Json, OptionalJson, Path}, groups::Membership, now_ms, products::Capabilities, routes::{light_address, power_level, request_all, set_light_color, set_light_power},
+    selector::Selector, web::AppState, Light, LightStatus, Serial,
+};
+
+// a local stand-in for the subset of LIFX's cloud HTTP API that scripts use, mounted at /v1.
+// the Authorization header those scripts send is ignored
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/lights/:selector", get(list_lights))
+        .route("/lights/:selector/state", put(set_state))
+        .route("/lights/:selector/toggle", post(toggle))
+}
+
+#[derive(Serialize)]
+pub struct CloudColor {
+    hue: f64,
+    saturation: f64,
+    kelvin: u16,
+}
+
+#[derive(Serialize)]
+pub struct CloudCollection {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct CloudProduct {
+    name: String,
+    identifier: String,
+    company: &'static str,
+    vendor_id: Option<u32>,
+    product_id: Option<u32>,
+    capabilities: Option<CloudCapabilities>,
+}
+
+#[derive(Serialize)]
+pub struct CloudCapabilities {
+    has_color: bool,
+    has_variable_color_temp: bool,
+    has_ir: bool,
+    has_hev: bool,
+    has_chain: bool,
+    has_matrix: bool,
+    has_multizone: bool,
+    has_extended_multizone: bool,
+    min_kelvin: Option<u16>,
+    max_kelvin: Option<u16>,
+}
+
+// a light the way the cloud API describes it
+#[derive(Serialize)]
+pub struct CloudLight {
+    id: String,
+    uuid: String,
+    label: String,
+    connected: bool,
+    power: &'static str,
+    color: CloudColor,
+    brightness: f64,
+    group: Option<CloudCollection>,
+    location: Option<CloudCollection>,
+    product: CloudProduct,
+    last_seen: Option<String>,
+    seconds_since_seen: Option<u64>,
+}
+
+fn fraction(value: Option<u16>) -> f64 {
+    value.unwrap_or(0) as f64 / 65535.0
+}
+
+fn collection(membership: &Option<Membership>) -> Option<CloudCollection> {
+    membership.as_ref().map(|membership| CloudCollection {
+        id: membership.id.to_string().replace('-', ""),
+        name: membership.label.clone(),
+    })
+}
+
+impl From<&Capabilities> for CloudCapabilities {
+    fn from(capabilities: &Capabilities) -> Self {
+        CloudCapabilities {
+            has_color: capabilities.color,
+            has_variable_color_temp: capabilities.kelvin_min != capabilities.kelvin_max,
+            has_ir: capabilities.infrared,
+            has_hev: capabilities.hev,
+            has_chain: capabilities.chain,
+            has_matrix: capabilities.matrix,
+            has_multizone: capabilities.multizone,
+            has_extended_multizone: capabilities.extended_multizone,
+            min_kelvin: capabilities.kelvin_min,
+            max_kelvin: capabilities.kelvin_max,
+        }
+    }
+}
+
+impl From<&Light> for CloudLight {
+    fn from(light: &Light) -> Self {
+        let name = light.product_name.clone().unwrap_or_else(|| "Unknown".to_string());
+
+        CloudLight {
+            id: light.serial.to_string(),
+            uuid: light.serial.to_string(),
+            label: light.label.clone().unwrap_or_default(),
+            connected: light.status == LightStatus::Online,
+            power: if light.power.is_some_and(|power| power > 0) { "on" } else { "off" },
+            color: CloudColor {
+                hue: fraction(light.hue) * 360.0,
+                saturation: fraction(light.saturation),
+                kelvin: light.kelvin.unwrap_or(3500),
+            },
+            brightness: fraction(light.brightness),
+            group: collection(&light.group),
+            location: collection(&light.location),
+            product: CloudProduct {
+                identifier: name.to_lowercase().replace(' ', "_"),
+                name,
+                company: "LIFX",
+                vendor_id: light.vendor,
+                product_id: light.product,
+                capabilities: light.capabilities.as_ref().map(CloudCapabilities::from),
+            },
+            last_seen: light.last_seen_ms.map(format_timestamp),
+            seconds_since_seen: light.last_seen_ms.map(|last_seen_ms| now_ms().saturating_sub(last_seen_ms) / 1000),
+        }
+    }
+}
+
+// milliseconds since the epoch as an ISO 8601 UTC timestamp
+fn format_timestamp(ms: u64) -> String {
+    let secs = ms / 1000;
+
+    let days = (secs / 86400) as i64;
+    let (hour, minute, second) = ((secs % 86400) / 3600, (secs % 3600) / 60, secs % 60);
+
+    // days since the epoch to a civil date, from Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+async fn select(state: &AppState, selector: &str) -> Result<Vec<Light>, ApiError> {
+    let parsed: Selector = selector.parse().map_err(ApiError::InvalidValue)?;
+
+    let lights = parsed.select(&state.lights).await;
+    if lights.is_empty() {
+        return Err(ApiError::NoLightsMatch(selector.to_string()));
+    }
+
+    Ok(lights)
+}
+
+pub async fn list_lights(state: State<AppState>, Path(selector): Path<String>) -> Result<Json<Vec<CloudLight>>, ApiError> {
+    let lights = select(&state, &selector).await?;
+
+    Ok(Json(lights.iter().map(CloudLight::from).collect()))
+}
+
+#[derive(Serialize)]
+pub struct CloudResult {
+    id: String,
+    label: String,
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct CloudResults {
+    results: Vec<CloudResult>,
+}
+
+fn result_status(result: &Result<(), ApiError>) -> &'static str {
+    match result {
+        Ok(()) => "ok",
+        Err(ApiError::LightUnreachable(_)) => "timed_out",
+        Err(_) => "error",
+    }
+}
+
+// runs a change on every selected light at once, offline lights are reported rather than waited on
+async fn apply_to_lights<F, Fut>(state: &AppState, lights: Vec<Light>, apply: F) -> CloudResults
+where
+    F: Fn(AppState, Serial) -> Fut,
+    Fut: std::future::Future<Output = Result<(), ApiError>> + Send + 'static,
+{
+    let mut results = Vec::new();
+    let mut changes = JoinSet::new();
+
+    for light in lights {
+        let label = light.label.clone().unwrap_or_default();
+
+        if light.status == LightStatus::Offline {
+            results.push(CloudResult { id: light.serial.to_string(), label, status: "offline" });
+            continue;
+        }
+
+        let change = apply(state.clone(), light.serial);
+        changes.spawn(async move { (light.serial, label, change.await) });
+    }
+
+    while let Some(change) = changes.join_next().await {
+        if let Ok((serial, label, result)) = change {
+            if let Err(e) = &result {
+                log::debug!("Failed to change {}: {}", serial, e);
+            }
+
+            results.push(CloudResult { id: serial.to_string(), label, status: result_status(&result) });
+        }
+    }
+
+    results.sort_by(|a, b| a.id.cmp(&b.id));
+
+    CloudResults { results }
+}
+
+#[derive(Deserialize)]
+pub struct StateRequest {
+    power: Option<String>,
+    color: Option<String>,
+    brightness: Option<f64>,
+    // seconds, as in the cloud API
+    duration: Option<f64>,
+    // answer straight away without waiting for the lights
+    #[serde(default)]
+    fast: bool,
+}
+
+fn duration_ms(duration: Option<f64>) -> Result<Option<u32>, ApiError> {
+    match duration {
+        Some(duration) if !(0.0..=3_155_760_000.0).contains(&duration) => {
+            Err(ApiError::InvalidValue(format!("duration out of range: {}", duration)))
+        }
+        Some(duration) => Ok(Some((duration * 1000.0).round().min(u32::MAX as f64) as u32)),
+        None => Ok(None),
+    }
+}
+
+fn multi_status(results: CloudResults) -> Response {
+    (StatusCode::MULTI_STATUS, Json(results)).into_response()
+}
+
+pub async fn set_state(state: State<AppState>, Path(selector): Path<String>, Json(request): Json<StateRequest>) -> Result<Response, ApiError> {
+    log::debug!("HTTP API state request for {}", selector);
+
+    let on = match request.power.as_deref() {
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(power) => return Err(ApiError::InvalidValue(format!("power must be on or off: {}", power))),
+        None => None,
+    };
+
+    let mut change = match &request.color {
+        Some(color) => color.parse::<ColorChange>().map_err(ApiError::InvalidValue)?,
+        None => ColorChange::default(),
+    };
+
+    if let Some(brightness) = request.brightness {
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(ApiError::InvalidValue(format!("brightness must be between 0 and 1: {}", brightness)));
+        }
+
+        change.brightness = Some((brightness * 65535.0).round() as u16);
+    }
+
+    if on.is_none() && change.is_empty() {
+        return Err(ApiError::InvalidValue("a state change needs a power, color or brightness".to_string()));
+    }
+
+    let duration_ms = duration_ms(request.duration)?;
+    let lights = select(&state, &selector).await?;
+
+    let apply = move |state: AppState, serial: Serial| async move {
+        if !change.is_empty() {
+            set_light_color(&state, serial, change, duration_ms).await?;
+        }
+
+        if let Some(on) = on {
+            let address = light_address(&state, serial).await?;
+            set_light_power(&state, serial, address, on, duration_ms).await?;
+        }
+
+        Ok(())
+    };
+
+    if request.fast {
+        let state = state.0.clone();
+        tokio::spawn(async move { apply_to_lights(&state, lights, apply).await });
+
+        return Ok(StatusCode::ACCEPTED.into_response());
+    }
+
+    Ok(multi_status(apply_to_lights(&state, lights, apply).await))
+}
+
+#[derive(Deserialize, Default)]
+pub struct ToggleRequest {
+    duration: Option<f64>,
+}
+
+// if any selected light is on they are all turned off, otherwise they are all turned on
+pub async fn toggle(state: State<AppState>, Path(selector): Path<String>, OptionalJson(request): OptionalJson<ToggleRequest>) -> Result<Response, ApiError> {
+    log::debug!("HTTP API toggle request for {}", selector);
+
+    let duration_ms = duration_ms(request.duration)?;
+
+    let lights = select(&state, &selector).await?;
+
+    let requests = lights
+        .iter()
+        .filter(|light| light.status != LightStatus::Offline)
+        .filter_map(|light| light.address.map(|address| (light.serial, address, Message::GetLightPower)))
+        .collect();
+
+    let any_on = request_all(&state, requests)
+        .await
+        .iter()
+        .any(|(_, response)| response.as_ref().ok().and_then(power_level).is_some_and(|level| level > 0));
+
+    let apply = move |state: AppState, serial: Serial| async move {
+        let address = light_address(&state, serial).await?;
+        set_light_power(&state, serial, address, !any_on, duration_ms).await
+    };
+
+    Ok(multi_status(apply_to_lights(&state, lights, apply).await))
+}
@@ -1,51 +1,105 @@
 use std::{
-    collections::HashMap, sync::{atomic::AtomicBool, Arc},
+    collections::HashMap, fmt, net::SocketAddr, str::FromStr, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{SystemTime, UNIX_EPOCH},
 };
 
 use lifx_lan::{messages::Message, request_options::LifxRequestOptions};
 
+use clap::Parser;
 use ctrlc;
 use log::{debug, info};
-use serde::Serialize;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::{
     select,
-    sync::RwLock,
+    sync::{oneshot, watch, Notify, RwLock},
 };
 
 extern crate socket2;
 
 mod socket;
+mod client;
+mod ack;
+mod response;
+mod packet;
+mod scheduler;
+mod interfaces;
+mod exclusion;
 mod discovery;
+mod products;
+mod color;
+mod groups;
 mod onboard;
 
 mod web;
 mod routes;
+mod error;
+mod extract;
+mod api_v1;
+mod openapi;
+mod selector;
+mod lifx_http;
+mod events;
+mod ws;
+mod scenes;
+mod registry;
+mod config;
+mod storage;
 
 #[tokio::main]
 async fn main() {
+    let args = config::Args::parse();
+
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
     );
 
-    let is_terminating = Arc::new(AtomicBool::new(false));
+    let config = match config::Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid configuration:\n{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let registry = registry::Registry::new(config.storage.registry_path.clone());
+    let scenes = Arc::new(scenes::SceneStore::load(config.storage.scenes_path.clone()));
+
+    let (config_tx, config) = watch::channel(config);
+    tokio::spawn(config::watch_file(args, config_tx));
+
+    let is_terminating = Arc::new(Shutdown::new());
 
     let is_terminating_clone = is_terminating.clone();
     ctrlc::set_handler(move || {
         debug!("Received Ctrl-C signal.");
 
-        is_terminating_clone.store(true, std::sync::atomic::Ordering::Release);
+        is_terminating_clone.trigger();
     })
     .expect("Error setting Ctrl-C handler");
 
-    let lights = Arc::new(RwLock::new(HashMap::<String, Arc<RwLock<Light>>>::new()));
-    let (tx, socket_handle) = socket::create_socket(lights.clone(), is_terminating.clone());
+    // identifies our packets and the replies to them, 0 and 1 are avoided as devices may broadcast those replies
+    let source: u32 = rand::thread_rng().gen_range(2..=u32::MAX);
+    info!("Using source id {}.", source);
+
+    let lights: Lights = Arc::new(RwLock::new(registry.load()));
+    let events = events::EventBus::new();
+
+    // discovery watches the network interfaces, the socket rebinds when they change
+    let (interfaces_tx, interfaces) = watch::channel(Vec::new());
+
+    let (tx, socket_handle) = socket::create_socket(lights.clone(), events.clone(), is_terminating.flag(), source, config.clone(), interfaces);
+
+    let client = client::LifxClient::new(tx, source);
+    let (discovery, scan_requests) = discovery::DiscoveryHandle::new();
 
     let light_discovery_handle = tokio::spawn(
-        discovery::broadcast_discovery_requests(tx.clone(), is_terminating.clone())
+        discovery::run_discovery(client.clone(), lights.clone(), events.clone(), is_terminating.clone(), config.clone(), scan_requests, interfaces_tx)
     );
     log::info!("Started discovery thread.");
 
-    let webserver_handle = tokio::spawn(web::start_webserver(tx.clone(), lights.clone()));
+    tokio::spawn(registry.clone().run(lights.clone(), events.clone()));
+
+    let webserver_handle = tokio::spawn(web::start_webserver(client, discovery, events, scenes, lights.clone(), config));
     log::info!("Webserver thread started.");
 
     select! {
@@ -59,20 +113,182 @@ async fn main() {
             info!("Webserver thread exited.");
         }
     }
+
+    if let Err(e) = registry.save(&lights).await {
+        log::error!("Failed to save light registry: {}", e);
+    }
+}
+
+// set once on Ctrl-C, can be checked or awaited by every task
+struct Shutdown {
+    triggered: Arc<AtomicBool>,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            triggered: Arc::new(AtomicBool::new(false)),
+            notify: Notify::new(),
+        }
+    }
+
+    // the bare flag, for code that only ever checks it
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.triggered.clone()
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::Acquire)
+    }
+
+    pub async fn wait(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+
+        // register before checking the flag so a trigger in between isn't missed
+        notified.as_mut().enable();
+
+        if self.is_triggered() {
+            return;
+        }
+
+        notified.await;
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct Request {
     pub options: LifxRequestOptions,
     pub message: Message,
 
     pub target: String,
+
+    // when set, the socket requests an acknowledgement, retransmits until it gets one and reports the outcome here
+    pub ack: Option<oneshot::Sender<ack::Delivery>>,
+    // when set, the device's reply to this request is forwarded here
+    pub response: Option<oneshot::Sender<Message>>,
+
+    // sent to a configured host rather than a broadcast address, whoever answers is marked manually configured
+    pub probe: bool,
+}
+
+type Lights = Arc<RwLock<HashMap<Serial, Arc<RwLock<Light>>>>>;
+
+// the 6 byte device serial (MAC address) from the frame header, used as a light's identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Serial(pub [u8; 6]);
+
+impl Serial {
+    pub fn from_target(target: &[u8; 8]) -> Serial {
+        let mut serial = [0u8; 6];
+        serial.copy_from_slice(&target[..6]);
+
+        Serial(serial)
+    }
+
+    pub fn to_target(&self) -> [u8; 8] {
+        let mut target = [0u8; 8];
+        target[..6].copy_from_slice(&self.0);
+
+        target
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 6]
+    }
+}
+
+impl fmt::Display for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Serial {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.chars().filter(|c| *c != ':' && *c != '-').collect();
+
+        if hex.len() != 12 || !hex.is_ascii() {
+            return Err(format!("invalid serial: {}", s));
+        }
+
+        let mut serial = [0u8; 6];
+        for (i, byte) in serial.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid serial: {}", s))?;
+        }
+
+        Ok(Serial(serial))
+    }
+}
+
+impl Serialize for Serial {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl<'de> Deserialize<'de> for Serial {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LightStatus {
+    // loaded from the registry file and not heard from since startup
+    Stale,
+    // replying to polls
+    Online,
+    // missed a few polls, may just be packet loss
+    Unreachable,
+    // hasn't replied for long enough that it's probably switched off at the wall
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Light {
+    pub serial: Serial,
+    // last address the light replied from, updated on every response
+    pub address: Option<SocketAddr>,
+    // name of the network interface the light was found on, commands to it are sent from there
+    pub interface: Option<String>,
+    // found by probing a configured host or range rather than by broadcast
+    pub manually_configured: bool,
+
     pub label: Option<String>,
     pub firmware_version: Option<String>,
+    pub firmware_major: Option<u16>,
+    pub firmware_minor: Option<u16>,
+
+    pub vendor: Option<u32>,
+    pub product: Option<u32>,
+    // looked up from vendor and product, unset for devices missing from the product registry
+    #[serde(skip_deserializing)]
+    pub product_name: Option<String>,
+    #[serde(skip_deserializing)]
+    pub capabilities: Option<products::Capabilities>,
+
+    pub group: Option<groups::Membership>,
+    pub location: Option<groups::Membership>,
 
     pub power: Option<u16>,
 
@@ -84,13 +300,29 @@ struct Light {
 
     // timestamp in ms of when the light was last seen online (sent a response)
     pub last_seen_ms: Option<u64>,
+    pub status: LightStatus,
 }
 
-impl Default for Light {
-    fn default() -> Self {
+impl Light {
+    pub fn new(serial: Serial) -> Self {
         Light {
+            serial,
+            address: None,
+            interface: None,
+            manually_configured: false,
+
             label: None,
             firmware_version: None,
+            firmware_major: None,
+            firmware_minor: None,
+
+            vendor: None,
+            product: None,
+            product_name: None,
+            capabilities: None,
+
+            group: None,
+            location: None,
 
             power: None,
 
@@ -101,6 +333,37 @@ impl Default for Light {
             kelvin: None,
 
             last_seen_ms: None,
+            status: LightStatus::Online,
+        }
+    }
+
+    pub fn hsbk(&self) -> Option<color::Hsbk> {
+        Some(color::Hsbk {
+            hue: self.hue?,
+            saturation: self.saturation?,
+            brightness: self.brightness?,
+            kelvin: self.kelvin?,
+        })
+    }
+
+    // recomputes the product name and capabilities, as upgrades depend on both the product and the firmware
+    pub fn refresh_product(&mut self) {
+        let (Some(vendor), Some(product)) = (self.vendor, self.product) else {
+            return;
+        };
+
+        let firmware = self.firmware_major.zip(self.firmware_minor);
+
+        match products::lookup(vendor, product, firmware) {
+            Some(entry) => {
+                self.product_name = Some(entry.name);
+                self.capabilities = Some(entry.capabilities);
+            }
+            None => {
+                log::debug!("Light {} has unknown product {}:{}", self.serial, vendor, product);
+                self.product_name = None;
+                self.capabilities = None;
+            }
         }
     }
 }
\ No newline at end of file
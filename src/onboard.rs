@@ -1,6 +1,6 @@
 extern crate lifx_lan;
 
-use std::{io::{self, Write}, net::{SocketAddr, TcpStream}, str::FromStr, time::Duration};
+use std::{io::{self, Write}, net::{SocketAddr, TcpStream}, time::Duration};
 
 // use heapless::String;
 
@@ -8,7 +8,7 @@ use lifx_lan::{messages::Message, request_options::LifxRequestOptions, serialize
 use native_tls::TlsConnector;
 
 fn pad_with_nulls(input: &str, target_length: usize) -> String {
-    let mut padded = String::from_str(input).unwrap();
+    let mut padded = input.to_string();
     while padded.len() < target_length {
         // Append null bytes until the string reaches the target length
         padded.push('\0'); // Use unwrap since we are sure of the capacity
@@ -16,17 +16,15 @@ fn pad_with_nulls(input: &str, target_length: usize) -> String {
     padded
 }
 
-pub fn send_onboarding_request(mut ssid: String, mut password: String) -> Result<(), io::Error> {
+pub fn send_onboarding_request(light_address: SocketAddr, mut ssid: String, mut password: String, source: u32) -> Result<(), io::Error> {
     ssid = pad_with_nulls(&ssid, 32);
     password = pad_with_nulls(&password, 64);
 
-    let light_address: SocketAddr = "172.16.0.1:56700".parse().unwrap();
-
     let mut message_buffer = [0u8; 36 + 32 + 64 + 2];
 
     let req_options = LifxRequestOptions {
         tagged: true,
-        source: 0,
+        source,
         target: [0; 8],
         ack_required: true,
         res_required: true,
@@ -44,24 +42,24 @@ pub fn send_onboarding_request(mut ssid: String, mut password: String) -> Result
 
     let tcp_stream = TcpStream::connect_timeout(&light_address, Duration::from_secs(3))?;
 
-    tcp_stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
-    tcp_stream.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+    tcp_stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    tcp_stream.set_write_timeout(Some(Duration::from_secs(5)))?;
 
     let connector = TlsConnector::builder()
         .danger_accept_invalid_certs(true) 
         .danger_accept_invalid_hostnames(true) 
         .build()
-        .expect("Failed to build TLS connector");
+        .map_err(|e| io::Error::other(format!("failed to build TLS connector: {}", e)))?;
 
     let mut tls_stream = connector
         .connect("hostname-does-not-matter", tcp_stream)
-        .expect("TLS handshake failed");
+        .map_err(|e| io::Error::other(format!("TLS handshake failed: {}", e)))?;
 
-    tls_stream.write_all(&message_buffer).unwrap();
+    tls_stream.write_all(&message_buffer)?;
 
     println!("Onboarding request sent.");
 
-    tls_stream.shutdown().unwrap();
+    tls_stream.shutdown()?;
 
     return Ok(());
 }
\ No newline at end of file
@@ -0,0 +1,432 @@
+use serde_json::{json, Value};
+
+use crate::color::{KELVIN_MAX, KELVIN_MIN};
+
+// the OpenAPI 3 description of /api/v1, keep it in step with api_v1 and the types it returns
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "LIFX desktop app API",
+            "version": "1.0.0",
+            "description": "Control of LIFX lights on the local network."
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "paths": {
+            "/lights": {
+                "get": {
+                    "summary": "List lights",
+                    "operationId": "listLights",
+                    "parameters": [
+                        query_parameter("label", "Only lights with this label, case insensitive", json!({ "type": "string" })),
+                        query_parameter("group", "Only lights in the group with this label or id", json!({ "type": "string" })),
+                        query_parameter("location", "Only lights in the location with this label or id", json!({ "type": "string" })),
+                        query_parameter("online", "Only lights that are, or aren't, answering polls", json!({ "type": "boolean" }))
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The matching lights, ordered by serial",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Light" } }
+                                }
+                            }
+                        },
+                        "400": error_response("A filter doesn't parse")
+                    }
+                }
+            },
+            "/lights/{id}": {
+                "get": {
+                    "summary": "Get a light",
+                    "operationId": "getLight",
+                    "parameters": [serial_parameter()],
+                    "responses": {
+                        "200": light_response("The light"),
+                        "400": error_response("The id isn't a serial"),
+                        "404": error_response("No light with that serial")
+                    }
+                }
+            },
+            "/lights/{id}/state": {
+                "patch": {
+                    "summary": "Change a light's power and color",
+                    "operationId": "updateLightState",
+                    "parameters": [serial_parameter()],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/StateChange" }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": light_response("The light after the device confirmed the change"),
+                        "400": error_response("The id isn't a serial, the body doesn't parse, or the change is invalid or the light can't do it"),
+                        "404": error_response("No light with that serial"),
+                        "503": error_response("The LIFX socket is not running"),
+                        "504": error_response("The light did not respond")
+                    }
+                }
+            },
+            "/scenes": {
+                "get": {
+                    "summary": "List scenes",
+                    "operationId": "listScenes",
+                    "responses": {
+                        "200": {
+                            "description": "Every saved scene, ordered by name",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Scene" } }
+                                }
+                            }
+                        }
+                    }
+                },
+                "post": {
+                    "summary": "Save the current state of some lights as a scene",
+                    "operationId": "createScene",
+                    "requestBody": json_body("CreateScene"),
+                    "responses": {
+                        "200": scene_response("The new scene"),
+                        "400": error_response("The body doesn't parse, no name or lights were given, or a light hasn't reported its state yet"),
+                        "404": error_response("No light with one of the serials"),
+                        "409": error_response("A scene with that name already exists"),
+                        "500": error_response("The scene could not be saved")
+                    }
+                }
+            },
+            "/scenes/{name}": {
+                "get": {
+                    "summary": "Get a scene",
+                    "operationId": "getScene",
+                    "parameters": [scene_parameter()],
+                    "responses": {
+                        "200": scene_response("The scene"),
+                        "404": error_response("No scene with that name")
+                    }
+                },
+                "patch": {
+                    "summary": "Rename a scene or replace its lights",
+                    "operationId": "updateScene",
+                    "parameters": [scene_parameter()],
+                    "requestBody": json_body("SceneEdit"),
+                    "responses": {
+                        "200": scene_response("The scene after the edit"),
+                        "400": error_response("The body doesn't parse or the edit is invalid"),
+                        "404": error_response("No scene, or no light, with that name"),
+                        "409": error_response("Another scene already has the new name"),
+                        "500": error_response("The scene could not be saved")
+                    }
+                },
+                "delete": {
+                    "summary": "Delete a scene",
+                    "operationId": "deleteScene",
+                    "parameters": [scene_parameter()],
+                    "responses": {
+                        "200": scene_response("The deleted scene"),
+                        "404": error_response("No scene with that name"),
+                        "500": error_response("The scene could not be removed from disk")
+                    }
+                }
+            },
+            "/scenes/{name}/recall": {
+                "post": {
+                    "summary": "Set every light in a scene to its saved state",
+                    "operationId": "recallScene",
+                    "parameters": [scene_parameter()],
+                    "requestBody": {
+                        "required": false,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "additionalProperties": false,
+                                    "properties": {
+                                        "duration_ms": { "type": "integer", "minimum": 0, "description": "How long the lights fade over" }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Which lights took the scene and which failed",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/RecallReport" } } }
+                        },
+                        "400": error_response("The body isn't empty and doesn't parse"),
+                        "404": error_response("No scene with that name")
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Light": {
+                    "type": "object",
+                    "required": ["serial", "manually_configured", "status"],
+                    "properties": {
+                        "serial": { "type": "string", "example": "d073d5001234" },
+                        "address": { "type": "string", "nullable": true, "example": "192.168.1.20:56700" },
+                        "interface": { "type": "string", "nullable": true },
+                        "manually_configured": { "type": "boolean" },
+                        "label": { "type": "string", "nullable": true },
+                        "firmware_version": { "type": "string", "nullable": true },
+                        "firmware_major": { "type": "integer", "nullable": true },
+                        "firmware_minor": { "type": "integer", "nullable": true },
+                        "vendor": { "type": "integer", "nullable": true },
+                        "product": { "type": "integer", "nullable": true },
+                        "product_name": { "type": "string", "nullable": true },
+                        "capabilities": { "allOf": [{ "$ref": "#/components/schemas/Capabilities" }], "nullable": true },
+                        "group": { "allOf": [{ "$ref": "#/components/schemas/Membership" }], "nullable": true },
+                        "location": { "allOf": [{ "$ref": "#/components/schemas/Membership" }], "nullable": true },
+                        "power": u16_schema("0 when off, 65535 when on"),
+                        "hue": u16_schema("0 to 65535 covering 0 to 360 degrees"),
+                        "saturation": u16_schema("0 to 65535"),
+                        "brightness": u16_schema("0 to 65535"),
+                        "kelvin": { "type": "integer", "nullable": true },
+                        "last_seen_ms": { "type": "integer", "format": "int64", "nullable": true },
+                        "status": {
+                            "type": "string",
+                            "enum": ["stale", "online", "unreachable", "offline"],
+                            "description": "Stale lights were loaded from the last run and haven't replied since"
+                        }
+                    }
+                },
+                "Capabilities": {
+                    "type": "object",
+                    "properties": {
+                        "color": { "type": "boolean" },
+                        "kelvin_min": { "type": "integer", "nullable": true },
+                        "kelvin_max": { "type": "integer", "nullable": true },
+                        "multizone": { "type": "boolean" },
+                        "extended_multizone": { "type": "boolean" },
+                        "matrix": { "type": "boolean" },
+                        "chain": { "type": "boolean" },
+                        "infrared": { "type": "boolean" },
+                        "hev": { "type": "boolean" },
+                        "relays": { "type": "boolean" },
+                        "buttons": { "type": "boolean" }
+                    }
+                },
+                "Membership": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "label": { "type": "string" },
+                        "updated_at": { "type": "integer", "format": "int64", "description": "Nanoseconds since the epoch" }
+                    }
+                },
+                "StateChange": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "description": "Fields left out keep their current value, raw values override the color string",
+                    "properties": {
+                        "power": { "type": "string", "enum": ["on", "off"] },
+                        "color": {
+                            "type": "string",
+                            "description": "A hex, rgb:, hsb:, kelvin:, xy: or named color, optionally followed by a brightness",
+                            "example": "2700K at 40%"
+                        },
+                        "hue": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                        "saturation": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                        "brightness": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                        "kelvin": { "type": "integer", "minimum": KELVIN_MIN, "maximum": KELVIN_MAX },
+                        "duration_ms": { "type": "integer", "minimum": 0, "description": "How long the change fades over" }
+                    }
+                },
+                "SceneLight": {
+                    "type": "object",
+                    "required": ["serial", "on", "color"],
+                    "properties": {
+                        "serial": { "type": "string", "example": "d073d5001234" },
+                        "on": { "type": "boolean" },
+                        "color": {
+                            "type": "object",
+                            "required": ["hue", "saturation", "brightness", "kelvin"],
+                            "properties": {
+                                "hue": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                                "saturation": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                                "brightness": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                                "kelvin": { "type": "integer", "minimum": KELVIN_MIN, "maximum": KELVIN_MAX }
+                            }
+                        }
+                    }
+                },
+                "Scene": {
+                    "type": "object",
+                    "required": ["name", "lights", "created_at_ms", "updated_at_ms"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "lights": { "type": "array", "items": { "$ref": "#/components/schemas/SceneLight" } },
+                        "created_at_ms": { "type": "integer", "format": "int64" },
+                        "updated_at_ms": { "type": "integer", "format": "int64" }
+                    }
+                },
+                "CreateScene": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["name", "lights"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "lights": { "type": "array", "items": { "type": "string" }, "description": "Serials of the lights to capture" }
+                    }
+                },
+                "SceneEdit": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "description": "Give either lights or capture to replace the scene's lights",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "lights": { "type": "array", "items": { "$ref": "#/components/schemas/SceneLight" } },
+                        "capture": { "type": "array", "items": { "type": "string" }, "description": "Serials of the lights to capture again" }
+                    }
+                },
+                "RecallReport": {
+                    "type": "object",
+                    "required": ["scene", "applied", "failed"],
+                    "properties": {
+                        "scene": { "type": "string" },
+                        "applied": { "type": "array", "items": { "type": "string" } },
+                        "failed": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["serial", "error", "message"],
+                                "properties": {
+                                    "serial": { "type": "string" },
+                                    "error": { "type": "string" },
+                                    "message": { "type": "string" }
+                                }
+                            }
+                        }
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "message"],
+                    "properties": {
+                        "error": { "type": "string", "example": "unknown_light" },
+                        "message": { "type": "string" }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": schema })
+}
+
+fn serial_parameter() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "The light's serial, 12 hex digits optionally separated by colons or dashes",
+        "schema": { "type": "string", "example": "d0:73:d5:00:12:34" }
+    })
+}
+
+fn scene_parameter() -> Value {
+    json!({ "name": "name", "in": "path", "required": true, "schema": { "type": "string" } })
+}
+
+fn json_body(schema: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": { "$ref": format!("#/components/schemas/{}", schema) } } }
+    })
+}
+
+fn u16_schema(description: &str) -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 65535, "nullable": true, "description": description })
+}
+
+fn light_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Light" } } }
+    })
+}
+
+fn scene_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Scene" } } }
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } }
+    })
+}
+
+// the schemas are written by hand, these catch them drifting from the types they describe
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde::Serialize;
+
+    use super::*;
+    use crate::{color::Hsbk, groups::{CollectionId, Membership}, products::Capabilities, scenes::{Scene, SceneLight}, Light, Serial};
+
+    const SERIAL: Serial = Serial([0xd0, 0x73, 0xd5, 0x00, 0x12, 0x34]);
+
+    fn serialized_keys(value: &impl Serialize) -> BTreeSet<String> {
+        match serde_json::to_value(value).unwrap() {
+            Value::Object(fields) => fields.keys().cloned().collect(),
+            other => panic!("{} is not an object", other),
+        }
+    }
+
+    fn schema_keys(schema: &Value) -> BTreeSet<String> {
+        let keys: BTreeSet<String> = schema["properties"].as_object().unwrap().keys().cloned().collect();
+
+        for required in schema["required"].as_array().into_iter().flatten() {
+            assert!(keys.contains(required.as_str().unwrap()), "required {} isn't a property", required);
+        }
+
+        keys
+    }
+
+    fn component(name: &str) -> Value {
+        document()["components"]["schemas"][name].clone()
+    }
+
+    #[test]
+    fn light_schema_matches_light() {
+        assert_eq!(schema_keys(&component("Light")), serialized_keys(&Light::new(SERIAL)));
+    }
+
+    #[test]
+    fn capabilities_schema_matches_capabilities() {
+        assert_eq!(schema_keys(&component("Capabilities")), serialized_keys(&Capabilities::default()));
+    }
+
+    #[test]
+    fn membership_schema_matches_membership() {
+        let membership = Membership { id: CollectionId([0; 16]), label: "Kitchen".to_string(), updated_at: 0 };
+
+        assert_eq!(schema_keys(&component("Membership")), serialized_keys(&membership));
+    }
+
+    #[test]
+    fn scene_schemas_match_scene() {
+        let light = SceneLight {
+            serial: SERIAL,
+            on: true,
+            color: Hsbk { hue: 0, saturation: 0, brightness: 65535, kelvin: 2700 },
+        };
+
+        assert_eq!(schema_keys(&component("SceneLight")), serialized_keys(&light));
+        assert_eq!(schema_keys(&component("SceneLight")["properties"]["color"]), serialized_keys(&light.color));
+        assert_eq!(schema_keys(&component("Scene")), serialized_keys(&Scene::new("Evening".to_string(), vec![light])));
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use lifx_lan::{serialize_lifx_packet, LifxRequestOptions, Message};
+
+pub const HEADER_SIZE: usize = 36;
+
+// comfortably larger than the biggest LIFX message (StateExtendedColorZones, 700 bytes)
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+// labels and group and location names are fixed size fields on the device
+pub const LABEL_SIZE: usize = 32;
+
+#[derive(Debug)]
+pub enum FrameError {
+    TooShort(usize),
+    Truncated { declared: usize, received: usize },
+    InvalidSize(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooShort(received) => write!(f, "packet of {} bytes is shorter than a header", received),
+            FrameError::Truncated { declared, received } => write!(f, "packet declares {} bytes but only {} were received", declared, received),
+            FrameError::InvalidSize(declared) => write!(f, "packet declares an invalid size of {} bytes", declared),
+        }
+    }
+}
+
+// the size field is the first two bytes of the frame header, little endian
+fn declared_size(buffer: &[u8]) -> usize {
+    u16::from_le_bytes([buffer[0], buffer[1]]) as usize
+}
+
+// checks a received datagram against the size in its header, returning just the frame
+pub fn received_frame(received: &[u8]) -> Result<&[u8], FrameError> {
+    if received.len() < HEADER_SIZE {
+        return Err(FrameError::TooShort(received.len()));
+    }
+
+    let declared = declared_size(received);
+
+    if declared < HEADER_SIZE {
+        return Err(FrameError::InvalidSize(declared));
+    }
+
+    if declared > received.len() {
+        return Err(FrameError::Truncated { declared, received: received.len() });
+    }
+
+    Ok(&received[..declared])
+}
+
+// serializes a packet into the buffer, returning only the bytes that belong on the wire
+pub fn serialize<'a>(options: &LifxRequestOptions, message: &Message, buffer: &'a mut [u8]) -> &'a [u8] {
+    serialize_lifx_packet(options, message, buffer);
+
+    let size = declared_size(buffer).clamp(HEADER_SIZE, buffer.len());
+
+    &buffer[..size]
+}
+
+// pads a label to its field size with null bytes, None when its UTF-8 encoding doesn't fit
+pub fn pad_label(label: &str) -> Option<String> {
+    if label.len() > LABEL_SIZE {
+        return None;
+    }
+
+    let mut padded = label.to_string();
+    padded.push_str(&"\0".repeat(LABEL_SIZE - label.len()));
+
+    Some(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a datagram of `len` bytes whose header declares `declared`
+    fn datagram(declared: u16, len: usize) -> Vec<u8> {
+        let mut datagram = vec![0u8; len];
+        datagram[..2].copy_from_slice(&declared.to_le_bytes());
+
+        datagram
+    }
+
+    fn options() -> LifxRequestOptions {
+        LifxRequestOptions {
+            tagged: false,
+            source: 1234,
+            target: [0xd0, 0x73, 0xd5, 0x00, 0x12, 0x34, 0, 0],
+            ack_required: false,
+            res_required: true,
+            sequence: 7,
+        }
+    }
+
+    #[test]
+    fn shorter_than_a_header_is_rejected() {
+        assert!(matches!(received_frame(&[0u8; 10]), Err(FrameError::TooShort(10))));
+        assert!(matches!(received_frame(&[]), Err(FrameError::TooShort(0))));
+    }
+
+    #[test]
+    fn truncated_frame_is_rejected() {
+        let received = datagram(40, 38);
+
+        assert!(matches!(received_frame(&received), Err(FrameError::Truncated { declared: 40, received: 38 })));
+    }
+
+    #[test]
+    fn size_smaller_than_a_header_is_rejected() {
+        let received = datagram(10, HEADER_SIZE + 4);
+
+        assert!(matches!(received_frame(&received), Err(FrameError::InvalidSize(10))));
+    }
+
+    #[test]
+    fn trailing_bytes_are_dropped() {
+        let received = datagram(38, 50);
+
+        assert_eq!(received_frame(&received).unwrap().len(), 38);
+        assert_eq!(received_frame(&datagram(38, 38)).unwrap().len(), 38);
+    }
+
+    #[test]
+    fn serialize_returns_only_the_declared_length() {
+        // leftovers from a longer packet mustn't go out on the wire
+        let mut buffer = [0xffu8; MAX_PACKET_SIZE];
+
+        let packet = serialize(&options(), &Message::GetService, &mut buffer);
+        assert_eq!(packet.len(), HEADER_SIZE);
+        assert_eq!(declared_size(packet), HEADER_SIZE);
+
+        let packet = serialize(&options(), &Message::SetPower { level: 65535 }, &mut buffer);
+        assert_eq!(packet.len(), HEADER_SIZE + 2);
+        assert_eq!(received_frame(packet).unwrap(), packet);
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+// a copy of products.json from https://github.com/LIFX/products, replace it with a newer one to pick up new devices
+const PRODUCTS_JSON: &str = include_str!("../data/products.json");
+
+static REGISTRY: OnceLock<Vec<Vendor>> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct Vendor {
+    vid: u32,
+    defaults: Features,
+    products: Vec<ProductEntry>,
+}
+
+#[derive(Deserialize)]
+struct ProductEntry {
+    pid: u32,
+    name: String,
+    features: Features,
+    #[serde(default)]
+    upgrades: Vec<Upgrade>,
+}
+
+// features a firmware version adds to a product
+#[derive(Deserialize)]
+struct Upgrade {
+    major: u16,
+    minor: u16,
+    features: Features,
+}
+
+// every field is optional, as entries only list what differs from the level below them
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Features {
+    color: Option<bool>,
+    temperature_range: Option<[u16; 2]>,
+    multizone: Option<bool>,
+    extended_multizone: Option<bool>,
+    matrix: Option<bool>,
+    chain: Option<bool>,
+    infrared: Option<bool>,
+    hev: Option<bool>,
+    relays: Option<bool>,
+    buttons: Option<bool>,
+}
+
+impl Features {
+    fn apply(&self, capabilities: &mut Capabilities) {
+        let flags = [
+            (self.color, &mut capabilities.color),
+            (self.multizone, &mut capabilities.multizone),
+            (self.extended_multizone, &mut capabilities.extended_multizone),
+            (self.matrix, &mut capabilities.matrix),
+            (self.chain, &mut capabilities.chain),
+            (self.infrared, &mut capabilities.infrared),
+            (self.hev, &mut capabilities.hev),
+            (self.relays, &mut capabilities.relays),
+            (self.buttons, &mut capabilities.buttons),
+        ];
+
+        for (feature, flag) in flags {
+            if let Some(feature) = feature {
+                *flag = feature;
+            }
+        }
+
+        if let Some([min, max]) = self.temperature_range {
+            capabilities.kelvin_min = Some(min);
+            capabilities.kelvin_max = Some(max);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub color: bool,
+    // unset on devices that don't emit light, like switches
+    pub kelvin_min: Option<u16>,
+    pub kelvin_max: Option<u16>,
+
+    pub multizone: bool,
+    pub extended_multizone: bool,
+    pub matrix: bool,
+    pub chain: bool,
+
+    pub infrared: bool,
+    pub hev: bool,
+
+    pub relays: bool,
+    pub buttons: bool,
+}
+
+impl Capabilities {
+    // checks a SetColor against what the device can do
+    pub fn check_color(&self, saturation: u16, kelvin: u16) -> Result<(), String> {
+        let (Some(kelvin_min), Some(kelvin_max)) = (self.kelvin_min, self.kelvin_max) else {
+            return Err("device does not emit light".to_string());
+        };
+
+        if !self.color && saturation != 0 {
+            return Err("device only supports white light".to_string());
+        }
+
+        if !(kelvin_min..=kelvin_max).contains(&kelvin) {
+            return Err(format!("kelvin must be between {} and {}", kelvin_min, kelvin_max));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Product {
+    pub name: String,
+    pub capabilities: Capabilities,
+}
+
+fn registry() -> &'static [Vendor] {
+    REGISTRY.get_or_init(|| serde_json::from_str(PRODUCTS_JSON).expect("Embedded products.json is invalid"))
+}
+
+// looks up a device's product, applying the upgrades its firmware has, if we know its firmware
+pub fn lookup(vendor: u32, product: u32, firmware: Option<(u16, u16)>) -> Option<Product> {
+    let vendor = registry().iter().find(|entry| entry.vid == vendor)?;
+    let entry = vendor.products.iter().find(|entry| entry.pid == product)?;
+
+    let mut capabilities = Capabilities::default();
+
+    vendor.defaults.apply(&mut capabilities);
+    entry.features.apply(&mut capabilities);
+
+    if let Some(firmware) = firmware {
+        for upgrade in &entry.upgrades {
+            if firmware >= (upgrade.major, upgrade.minor) {
+                upgrade.features.apply(&mut capabilities);
+            }
+        }
+    }
+
+    Some(Product {
+        name: entry.name.clone(),
+        capabilities,
+    })
+}
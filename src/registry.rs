@@ -0,0 +1,91 @@
+use std::{collections::HashMap, io, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::sync::{broadcast::error::RecvError, RwLock};
+
+use crate::{events::EventBus, storage::write_atomic, Light, LightStatus, Lights, Serial};
+
+// changes are batched up, a light polled every couple of seconds shouldn't mean a write every couple of seconds
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+// every light we know about, kept on disk so they're listed straight away after a restart
+#[derive(Clone)]
+pub struct Registry {
+    path: PathBuf,
+}
+
+impl Registry {
+    pub fn new(path: PathBuf) -> Self {
+        Registry { path }
+    }
+
+    // lights from the last run, stale until they reply again. a missing or unreadable file starts empty
+    pub fn load(&self) -> HashMap<Serial, Arc<RwLock<Light>>> {
+        let saved = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => match serde_json::from_str::<Vec<Light>>(&contents) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    log::error!("Ignoring unreadable light registry {}: {}", self.path.display(), e);
+                    return HashMap::new();
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return HashMap::new(),
+            Err(e) => {
+                log::error!("Failed to read light registry {}: {}", self.path.display(), e);
+                return HashMap::new();
+            }
+        };
+
+        log::info!("Loaded {} lights from {}", saved.len(), self.path.display());
+
+        saved
+            .into_iter()
+            .map(|mut light| {
+                light.status = LightStatus::Stale;
+                // the product registry may have changed since, so it's looked up again rather than stored
+                light.refresh_product();
+
+                (light.serial, Arc::new(RwLock::new(light)))
+            })
+            .collect()
+    }
+
+    pub async fn save(&self, lights: &Lights) -> io::Result<()> {
+        let mut saved = Vec::new();
+
+        for light in lights.read().await.values() {
+            saved.push(light.read().await.clone());
+        }
+
+        saved.sort_by_key(|light| light.serial.0);
+
+        write_atomic(&self.path, &saved).await
+    }
+
+    // saves whenever the registry changed, main saves once more on the way out
+    pub async fn run(self, lights: Lights, events: EventBus) {
+        let mut events = events.subscribe();
+        let mut changed = false;
+
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(_) | Err(RecvError::Lagged(_)) => changed = true,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => {
+                    if !changed {
+                        continue;
+                    }
+
+                    changed = false;
+
+                    if let Err(e) = self.save(&lights).await {
+                        log::error!("Failed to save light registry to {}: {}", self.path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use lifx_lan::Message;
+use tokio::sync::oneshot;
+
+use crate::Serial;
+
+// the State message a Get is answered with. Set messages aren't listed, their State reply shows the light
+// as it was before the change, so they're sent for an acknowledgement instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    Service,
+    Color,
+    Label,
+    Version,
+    HostFirmware,
+    Group,
+    Location,
+    LightPower,
+}
+
+impl ReplyKind {
+    pub fn expected_for(request: &Message) -> Option<ReplyKind> {
+        match request {
+            Message::GetService => Some(ReplyKind::Service),
+            Message::GetColor => Some(ReplyKind::Color),
+            Message::GetLabel => Some(ReplyKind::Label),
+            Message::GetVersion => Some(ReplyKind::Version),
+            Message::GetHostFirmware => Some(ReplyKind::HostFirmware),
+            Message::GetGroup => Some(ReplyKind::Group),
+            Message::GetLocation => Some(ReplyKind::Location),
+            Message::GetLightPower => Some(ReplyKind::LightPower),
+            _ => None,
+        }
+    }
+
+    pub fn of(reply: &Message) -> Option<ReplyKind> {
+        match reply {
+            Message::StateService { .. } => Some(ReplyKind::Service),
+            Message::LightState { .. } => Some(ReplyKind::Color),
+            Message::Label { .. } => Some(ReplyKind::Label),
+            Message::StateVersion { .. } => Some(ReplyKind::Version),
+            Message::HostFirmware { .. } => Some(ReplyKind::HostFirmware),
+            Message::StateGroup { .. } => Some(ReplyKind::Group),
+            Message::StateLocation { .. } => Some(ReplyKind::Location),
+            Message::StateLightPower { .. } => Some(ReplyKind::LightPower),
+            _ => None,
+        }
+    }
+}
+
+struct PendingResponse {
+    expects: ReplyKind,
+    responders: Vec<oneshot::Sender<Message>>,
+}
+
+// routes device replies back to whoever sent the request they answer, keyed by (target serial, sequence)
+pub struct ResponseTracker {
+    pending: HashMap<(Serial, u8), PendingResponse>,
+}
+
+impl ResponseTracker {
+    pub fn new() -> Self {
+        ResponseTracker {
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn track(&mut self, serial: Serial, sequence: u8, expects: ReplyKind, responders: Vec<oneshot::Sender<Message>>) {
+        let replaced = self.pending.insert((serial, sequence), PendingResponse { expects, responders });
+
+        // the sequence number wrapped around while the earlier request was still waiting
+        if replaced.is_some_and(|replaced| replaced.responders.iter().any(|responder| !responder.is_closed())) {
+            log::warn!("Sequence {} to {} reused before it was answered, giving up on the earlier request", sequence, serial);
+        }
+    }
+
+    // whoever is waiting on a reply, as long as it came from the light the request went to and is the kind it expects
+    pub fn take(&mut self, serial: Serial, sequence: u8, reply: &Message) -> Vec<oneshot::Sender<Message>> {
+        match self.pending.get(&(serial, sequence)) {
+            Some(pending) if ReplyKind::of(reply) == Some(pending.expects) => {
+                self.pending.remove(&(serial, sequence)).map(|pending| pending.responders).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // forget requests whose sender has stopped waiting, e.g. after timing out
+    pub fn remove_abandoned(&mut self) {
+        self.pending.retain(|_, pending| {
+            pending.responders.retain(|responder| !responder.is_closed());
+            !pending.responders.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(tracker: &mut ResponseTracker, serial: Serial, sequence: u8, request: Message) -> oneshot::Receiver<Message> {
+        let (tx, rx) = oneshot::channel();
+        tracker.track(serial, sequence, ReplyKind::expected_for(&request).unwrap(), vec![tx]);
+
+        rx
+    }
+
+    #[test]
+    fn reply_from_another_light_is_ignored() {
+        let mut tracker = ResponseTracker::new();
+        let _rx = track(&mut tracker, Serial([1; 6]), 3, Message::GetLightPower);
+
+        assert!(tracker.take(Serial([2; 6]), 3, &Message::StateLightPower { level: 0 }).is_empty());
+        assert_eq!(tracker.take(Serial([1; 6]), 3, &Message::StateLightPower { level: 0 }).len(), 1);
+    }
+
+    #[test]
+    fn reply_of_the_wrong_kind_is_ignored() {
+        let mut tracker = ResponseTracker::new();
+        let _rx = track(&mut tracker, Serial([1; 6]), 3, Message::GetLightPower);
+
+        assert!(tracker.take(Serial([1; 6]), 3, &Message::StateService { service: 1, port: 56700 }).is_empty());
+        assert_eq!(tracker.take(Serial([1; 6]), 3, &Message::StateLightPower { level: 65535 }).len(), 1);
+    }
+
+    #[test]
+    fn set_messages_expect_no_reply() {
+        assert_eq!(ReplyKind::expected_for(&Message::SetLightPower { level: 0, duration_ms: 0 }), None);
+    }
+}
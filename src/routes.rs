@@ -1,20 +1,24 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, net::SocketAddr};
 
-use axum::{body::Body, extract::{Query, State}, Json};
-use lifx_lan::{LifxRequestOptions, Message};
-use serde::Deserialize;
+use axum::extract::State;
+use lifx_lan::Message;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
 
-use crate::{onboard::send_onboarding_request, web::AppState, Light, Request};
+use crate::{
+    client::RequestError, color::{ColorChange, Hsbk, KELVIN_MAX, KELVIN_MIN}, discovery::ScanReport, error::ApiError, extract::{Json, Query}, groups::{self, Collection, CollectionId, CollectionKind, Member, Membership},
+    onboard::send_onboarding_request, packet::{pad_label, LABEL_SIZE}, web::AppState, Light, LightStatus, Serial,
+};
 
 pub async fn get_lights(state: State<AppState>) -> Json<HashMap<String, Light>> {
     let lights_read_guard = state.lights.read().await;
-    
+
     let mut lights = HashMap::new();
 
-    for (addr, light) in lights_read_guard.iter() {
+    for (serial, light) in lights_read_guard.iter() {
         let light = light.read().await;
 
-        lights.insert(addr.clone(), (*light).clone());
+        lights.insert(serial.to_string(), (*light).clone());
     }
 
     drop(lights_read_guard);
@@ -22,105 +26,254 @@ pub async fn get_lights(state: State<AppState>) -> Json<HashMap<String, Light>>
     Json(lights)
 }
 
+// the address commands to a light go to
+pub async fn light_address(state: &AppState, serial: Serial) -> Result<SocketAddr, ApiError> {
+    let lights = state.lights.read().await;
+
+    let light = lights.get(&serial).ok_or(ApiError::UnknownLight(serial))?;
+    let address = light.read().await.address.ok_or(ApiError::LightUnreachable(serial))?;
+
+    Ok(address)
+}
+
+// sends a request to a light and waits for the device's reply to it
+pub async fn request_from_light(state: &AppState, serial: Serial, address: SocketAddr, message: Message) -> Result<Message, ApiError> {
+    state.client.request(serial, address, message).await.map_err(|e| ApiError::from_request(serial, e))
+}
+
+// sends requests to several lights at once and collects each device's reply
+pub async fn request_all(state: &AppState, requests_to_send: Vec<(Serial, SocketAddr, Message)>) -> Vec<(Serial, Result<Message, RequestError>)> {
+    let mut requests = JoinSet::new();
+
+    for (serial, address, message) in requests_to_send {
+        let client = state.client.clone();
+
+        requests.spawn(async move { (serial, client.request(serial, address, message).await) });
+    }
+
+    let mut responses = Vec::new();
+
+    while let Some(response) = requests.join_next().await {
+        if let Ok(response) = response {
+            responses.push(response);
+        }
+    }
+
+    responses
+}
+
+// the Get that reads back what a Set changed
+fn confirming_request(message: &Message) -> Option<Message> {
+    match message {
+        Message::SetColor { .. } => Some(Message::GetColor),
+        Message::SetPower { .. } | Message::SetLightPower { .. } => Some(Message::GetLightPower),
+        Message::SetLabel { .. } => Some(Message::GetLabel),
+        Message::SetGroup { .. } => Some(Message::GetGroup),
+        Message::SetLocation { .. } => Some(Message::GetLocation),
+        _ => None,
+    }
+}
+
+// sends a Set message to a light, then once the device has acknowledged it asks for the state it ended up in,
+// which may differ from what was asked for if the device clamped or truncated it. the reply updates the cache
+pub async fn deliver_to_light(state: &AppState, serial: Serial, address: SocketAddr, message: Message) -> Result<(), ApiError> {
+    let confirmation = confirming_request(&message);
+
+    state.client.deliver(serial, address, message).await.map_err(|e| ApiError::from_request(serial, e))?;
+
+    if let Some(confirmation) = confirmation {
+        request_from_light(state, serial, address, confirmation).await?;
+    }
+
+    Ok(())
+}
+
+// sends Set messages to several lights at once, returning the lights that didn't confirm their change
+pub async fn deliver_to_lights(state: &AppState, changes: Vec<(Serial, SocketAddr, Message)>) -> Vec<(Serial, ApiError)> {
+    let mut deliveries = JoinSet::new();
+
+    for (serial, address, message) in changes {
+        let state = state.clone();
+
+        deliveries.spawn(async move { (serial, deliver_to_light(&state, serial, address, message).await) });
+    }
+
+    let mut failures = Vec::new();
+
+    while let Some(delivery) = deliveries.join_next().await {
+        if let Ok((serial, Err(e))) = delivery {
+            failures.push((serial, e));
+        }
+    }
+
+    failures.sort_by_key(|(serial, _)| serial.0);
+
+    failures
+}
+
+// a light counts as on at any level above zero, including partway through a fade
+pub fn power_level(response: &Message) -> Option<u16> {
+    match response {
+        Message::StateLightPower { level } | Message::StatePower { level } => Some(*level),
+        _ => None,
+    }
+}
+
+pub async fn light_snapshot(state: &AppState, serial: &Serial) -> Result<Json<Light>, ApiError> {
+    let lights = state.lights.read().await;
+
+    let light = lights.get(serial).ok_or(ApiError::UnknownLight(*serial))?;
+    let light = light.read().await.clone();
+
+    Ok(Json(light))
+}
+
 #[derive(Deserialize)]
 pub struct PowerRequest {
-    ip: String,
+    id: Serial,
+    on: bool,
+
+    // fade time, so a light can be dimmed down to off over minutes
+    duration_ms: Option<u32>,
 }
 
-// take IP address as query parameter
-pub async fn power(state: State<AppState>, query: Query<PowerRequest>) {
-    log::debug!("Toggle power request for {}", query.ip);
+pub async fn set_light_power(state: &AppState, serial: Serial, address: SocketAddr, on: bool, duration_ms: Option<u32>) -> Result<(), ApiError> {
+    deliver_to_light(state, serial, address, Message::SetLightPower {
+        level: if on { 65535 } else { 0 },
+        duration_ms: duration_ms.unwrap_or(0),
+    }).await
+}
 
-    let lights = state.lights.read().await;
-    
-    let mut light = lights.get(&query.ip).unwrap().write().await;
-    
-    let new_power = if light.power == Some(65535) {
-        0
-    } else {
-        65535
-    };
+// take light serial as query parameter, sending the same state twice leaves the light as it is
+pub async fn power(state: State<AppState>, query: Query<PowerRequest>) -> Result<Json<Light>, ApiError> {
+    log::debug!("Power request for {}: {}", query.id, if query.on { "on" } else { "off" });
 
-    light.power = Some(new_power);
+    let address = light_address(&state, query.id).await?;
 
-    drop(light);
-    drop(lights);
+    set_light_power(&state, query.id, address, query.on, query.duration_ms).await?;
+
+    light_snapshot(&state, &query.id).await
+}
+
+#[derive(Deserialize)]
+pub struct TogglePowerRequest {
+    id: Serial,
+
+    duration_ms: Option<u32>,
+}
+
+// asks the light for its power first rather than trusting the cache, which may be stale
+pub async fn toggle_light_power(state: &AppState, serial: Serial, duration_ms: Option<u32>) -> Result<(), ApiError> {
+    let address = light_address(state, serial).await?;
 
-    let mut guard = state.last_req_sequence.lock().await;
+    let response = request_from_light(state, serial, address, Message::GetLightPower).await?;
+    let level = power_level(&response).ok_or(ApiError::UnexpectedResponse(serial))?;
 
-    let sequence = *guard;
-    *guard = guard.wrapping_add(1);
+    set_light_power(state, serial, address, level == 0, duration_ms).await
+}
+
+pub async fn toggle_power(state: State<AppState>, query: Query<TogglePowerRequest>) -> Result<Json<Light>, ApiError> {
+    log::debug!("Toggle power request for {}", query.id);
+
+    toggle_light_power(&state, query.id, query.duration_ms).await?;
+
+    light_snapshot(&state, &query.id).await
+}
+
+// how long a color change fades for when the request doesn't say, power changes are instant by default
+const DEFAULT_COLOR_DURATION_MS: u32 = 450;
+
+// what a light that never reported its color is assumed to be showing
+const DEFAULT_COLOR: Hsbk = Hsbk {
+    hue: 0,
+    saturation: 0,
+    brightness: 65535,
+    kelvin: 3500,
+};
 
-    drop(guard);
+// combines a color string with raw HSBK values, the raw values win
+pub fn color_change(color: Option<ColorChange>, hue: Option<u16>, saturation: Option<u16>, brightness: Option<u16>, kelvin: Option<u16>) -> Result<ColorChange, ApiError> {
+    let mut change = color.unwrap_or_default();
 
-    state.tx.send(Request {
-        options: LifxRequestOptions {
-            tagged: true,
-            source: 0,
-            target: [0; 8],
-            ack_required: false,
-            res_required: true,
-            sequence,
-        },
-        message: Message::SetPower {
-            level: new_power
-        },
-        target: query.ip.clone(),
-    }).unwrap(); 
+    change.hue = hue.or(change.hue);
+    change.saturation = saturation.or(change.saturation);
+    change.brightness = brightness.or(change.brightness);
+    change.kelvin = kelvin.or(change.kelvin);
+
+    if change.is_empty() {
+        return Err(ApiError::InvalidValue("no color given".to_string()));
+    }
+
+    // the color string checks its own kelvin, a raw value has to be checked here
+    if let Some(kelvin) = change.kelvin {
+        if !(KELVIN_MIN..=KELVIN_MAX).contains(&kelvin) {
+            return Err(ApiError::InvalidValue(format!("kelvin must be between {} and {}", KELVIN_MIN, KELVIN_MAX)));
+        }
+    }
+
+    Ok(change)
 }
 
 #[derive(Deserialize)]
 pub struct ColorRequest {
-    ip: String,
+    id: Serial,
 
-    hue: u16,
-    saturation: u16,
-    brightness: u16,
+    duration_ms: Option<u32>,
 
-    kelvin: u16,
-}
+    // a color string like "#ff8800" or "2700K at 40%", see color::ColorChange
+    color: Option<ColorChange>,
+
+    hue: Option<u16>,
+    saturation: Option<u16>,
+    brightness: Option<u16>,
 
-pub async fn color(state: State<AppState>, query: Query<ColorRequest>) {
-    log::debug!("Color request for {}", query.ip);
+    kelvin: Option<u16>,
+}
 
+// applies a color change over the light's current color, checked against what the light can do
+pub async fn set_light_color(state: &AppState, serial: Serial, change: ColorChange, duration_ms: Option<u32>) -> Result<(), ApiError> {
     let lights = state.lights.read().await;
-    
-    let mut light = lights.get(&query.ip).unwrap().write().await;
-    
-    light.hue = Some(query.hue);
-    light.saturation = Some(query.saturation);
-    light.brightness = Some(query.brightness);
-    light.kelvin = Some(query.kelvin);
+
+    let light = lights.get(&serial).ok_or(ApiError::UnknownLight(serial))?.read().await;
+    let address = light.address.ok_or(ApiError::LightUnreachable(serial))?;
+
+    let target = change.apply(light.hsbk().unwrap_or(DEFAULT_COLOR));
+
+    // devices we couldn't identify yet are sent the command anyway
+    if let Some(capabilities) = &light.capabilities {
+        if let Err(reason) = capabilities.check_color(target.saturation, target.kelvin) {
+            return Err(ApiError::Unsupported { serial, reason });
+        }
+    }
 
     drop(light);
     drop(lights);
 
-    let mut guard = state.last_req_sequence.lock().await;
+    deliver_to_light(state, serial, address, Message::SetColor {
+        reserved_6: 1,
+        hue: target.hue,
+        saturation: target.saturation,
+        brightness: target.brightness,
+        kelvin: target.kelvin,
+        duration_ms: duration_ms.unwrap_or(DEFAULT_COLOR_DURATION_MS),
+    }).await
+}
 
-    let sequence = *guard;
-    *guard = guard.wrapping_add(1);
+pub async fn color(state: State<AppState>, query: Query<ColorRequest>) -> Result<Json<Light>, ApiError> {
+    log::debug!("Color request for {}", query.id);
 
-    drop(guard);
+    let change = color_change(query.color, query.hue, query.saturation, query.brightness, query.kelvin)?;
 
-    state.tx.send(Request {
-        options: LifxRequestOptions {
-            tagged: true,
-            source: 0,
-            target: [0; 8],
-            ack_required: false,
-            res_required: true,
-            sequence,
-        },
-        message: Message::SetColor {
-            reserved_6: 1,
-            hue: query.hue,
-            saturation: query.saturation,
-            brightness: query.brightness,
-            kelvin: query.kelvin,
-            duration_ms: 450,
-        },
-        target: query.ip.clone(),
-    }).unwrap(); 
+    set_light_color(&state, query.id, change, query.duration_ms).await?;
+
+    light_snapshot(&state, &query.id).await
+}
+
+// runs a burst scan and reports which devices were found, refreshed or went missing
+pub async fn discover(state: State<AppState>) -> Result<Json<ScanReport>, ApiError> {
+    log::debug!("Discovery scan request");
+
+    state.discovery.scan().await.map(Json).ok_or(ApiError::DiscoveryUnavailable)
 }
 
 #[derive(Deserialize)]
@@ -129,56 +282,370 @@ pub struct OnboardingRequest {
     password: String,
 }
 
-pub async fn trigger_onboarding(state: State<AppState>, body: Json<OnboardingRequest>) {
+pub async fn trigger_onboarding(state: State<AppState>, body: Json<OnboardingRequest>) -> Result<(), ApiError> {
     log::debug!("Onboarding request");
 
+    if body.ssid.is_empty() || body.ssid.len() > 32 {
+        return Err(ApiError::InvalidValue("ssid must be between 1 and 32 bytes".to_string()));
+    }
+
+    if body.password.len() > 64 {
+        return Err(ApiError::InvalidValue("password must be at most 64 bytes".to_string()));
+    }
+
     let ssid = body.ssid.clone();
     let password = body.password.clone();
-
-    send_onboarding_request(ssid, password).unwrap();
+    let source = state.client.source();
+    let address = state.config.borrow().onboarding_address();
+
+    // the onboarding connection is blocking TLS, keep it off the runtime's worker threads
+    tokio::task::spawn_blocking(move || send_onboarding_request(address, ssid, password, source))
+        .await
+        .map_err(|e| ApiError::OnboardingFailed(e.to_string()))?
+        .map_err(|e| ApiError::OnboardingFailed(e.to_string()))
 }
 
 #[derive(Deserialize)]
 pub struct NameRequest {
-    ip: String,
+    id: Serial,
     name: String,
 }
 
-pub async fn set_name(state: State<AppState>, body: Json<NameRequest>) {
-    log::debug!("Set name request for {}", body.ip);
+pub async fn set_name(state: State<AppState>, body: Json<NameRequest>) -> Result<Json<Light>, ApiError> {
+    log::debug!("Set name request for {}", body.id);
+
+    let label = pad_label(&body.name)
+        .ok_or_else(|| ApiError::InvalidLabel(format!("labels can be at most {} bytes", LABEL_SIZE)))?;
+
+    let address = light_address(&state, body.id).await?;
+
+    deliver_to_light(&state, body.id, address, Message::SetLabel { label }).await?;
+
+    light_snapshot(&state, &body.id).await
+}
+
+pub async fn get_groups(state: State<AppState>) -> Json<Vec<Collection>> {
+    Json(groups::collections(&state.lights, CollectionKind::Group).await)
+}
+
+pub async fn get_locations(state: State<AppState>) -> Json<Vec<Collection>> {
+    Json(groups::collections(&state.lights, CollectionKind::Location).await)
+}
+
+async fn collection_snapshot(state: &AppState, kind: CollectionKind, id: CollectionId) -> Result<Json<Collection>, ApiError> {
+    groups::collection(&state.lights, kind, id).await.map(Json).ok_or(ApiError::UnknownCollection(id))
+}
+
+#[derive(Deserialize)]
+pub struct GroupPowerRequest {
+    id: CollectionId,
+    on: bool,
+
+    duration_ms: Option<u32>,
+}
+
+// a light a group change left alone, because it's offline or can't do what was asked
+#[derive(Serialize)]
+pub struct SkippedLight {
+    pub serial: Serial,
+    pub reason: String,
+}
+
+// a light that didn't confirm a group change
+#[derive(Serialize)]
+pub struct FailedLight {
+    pub serial: Serial,
+    pub error: &'static str,
+    pub message: String,
+}
+
+// the group after a change, the lights that weren't part of it don't stop the rest from changing
+#[derive(Serialize)]
+pub struct GroupChangeResponse {
+    #[serde(flatten)]
+    pub group: Collection,
+    pub skipped: Vec<SkippedLight>,
+    pub failed: Vec<FailedLight>,
+}
+
+// an offline light would only hold the change up until it timed out, so it's skipped. fails when no light is left
+fn online_members(members: Vec<Member>, skipped: &mut Vec<SkippedLight>) -> Result<Vec<Member>, ApiError> {
+    let (online, offline): (Vec<Member>, Vec<Member>) = members.into_iter().partition(|member| member.status != LightStatus::Offline);
+
+    if online.is_empty() {
+        if let Some(first) = offline.first() {
+            return Err(ApiError::LightUnreachable(first.serial));
+        }
+    }
+
+    skipped.extend(offline.into_iter().map(|member| SkippedLight { serial: member.serial, reason: "offline".to_string() }));
+
+    Ok(online)
+}
+
+// sends the change to every light that's part of it. only fails when none of them confirmed it
+async fn change_group(state: &AppState, id: CollectionId, requests: Vec<(Serial, SocketAddr, Message)>, skipped: Vec<SkippedLight>) -> Result<Json<GroupChangeResponse>, ApiError> {
+    let attempted = requests.len();
+    let failures = deliver_to_lights(state, requests).await;
+
+    if attempted > 0 && failures.len() == attempted {
+        let (_, e) = failures.into_iter().next().unwrap();
+        return Err(e);
+    }
+
+    let failed = failures
+        .into_iter()
+        .map(|(serial, e)| FailedLight { serial, error: e.code(), message: e.to_string() })
+        .collect();
+
+    let Json(group) = collection_snapshot(state, CollectionKind::Group, id).await?;
+
+    Ok(Json(GroupChangeResponse { group, skipped, failed }))
+}
+
+async fn set_group_power(state: &AppState, id: CollectionId, members: Vec<Member>, skipped: Vec<SkippedLight>, on: bool, duration_ms: Option<u32>) -> Result<Json<GroupChangeResponse>, ApiError> {
+    let level = if on { 65535 } else { 0 };
+    let duration_ms = duration_ms.unwrap_or(0);
+
+    let requests = members
+        .into_iter()
+        .map(|member| (member.serial, member.address, Message::SetLightPower { level, duration_ms }))
+        .collect();
+
+    change_group(state, id, requests, skipped).await
+}
+
+pub async fn group_power(state: State<AppState>, query: Query<GroupPowerRequest>) -> Result<Json<GroupChangeResponse>, ApiError> {
+    log::debug!("Power request for group {}: {}", query.id, if query.on { "on" } else { "off" });
+
+    let members = groups::members(&state.lights, CollectionKind::Group, query.id).await;
+    if members.is_empty() {
+        return Err(ApiError::UnknownCollection(query.id));
+    }
+
+    let mut skipped = Vec::new();
+    let members = online_members(members, &mut skipped)?;
+
+    set_group_power(&state, query.id, members, skipped, query.on, query.duration_ms).await
+}
+
+#[derive(Deserialize)]
+pub struct GroupTogglePowerRequest {
+    id: CollectionId,
+
+    duration_ms: Option<u32>,
+}
+
+// turns the whole group off if any light in it is on, otherwise turns them all on.
+// every light is asked for its power first, lights that don't answer are left out of the decision
+pub async fn group_toggle_power(state: State<AppState>, query: Query<GroupTogglePowerRequest>) -> Result<Json<GroupChangeResponse>, ApiError> {
+    log::debug!("Toggle power request for group {}", query.id);
+
+    let members = groups::members(&state.lights, CollectionKind::Group, query.id).await;
+    if members.is_empty() {
+        return Err(ApiError::UnknownCollection(query.id));
+    }
+
+    let mut skipped = Vec::new();
+    let members = online_members(members, &mut skipped)?;
+
+    let requests = members.iter().map(|member| (member.serial, member.address, Message::GetLightPower)).collect();
+    let responses = request_all(&state, requests).await;
+
+    let levels: Vec<u16> = responses
+        .iter()
+        .filter_map(|(_, response)| response.as_ref().ok().and_then(power_level))
+        .collect();
+
+    if levels.is_empty() {
+        let serial = members[0].serial;
+        let error = responses.into_iter().find_map(|(_, response)| response.err()).unwrap_or(RequestError::NoResponse);
+
+        return Err(ApiError::from_request(serial, error));
+    }
+
+    let any_on = levels.iter().any(|level| *level > 0);
+
+    set_group_power(&state, query.id, members, skipped, !any_on, query.duration_ms).await
+}
+
+#[derive(Deserialize)]
+pub struct GroupColorRequest {
+    id: CollectionId,
+
+    duration_ms: Option<u32>,
+
+    color: Option<ColorChange>,
+
+    hue: Option<u16>,
+    saturation: Option<u16>,
+    brightness: Option<u16>,
+
+    kelvin: Option<u16>,
+}
+
+pub async fn group_color(state: State<AppState>, query: Query<GroupColorRequest>) -> Result<Json<GroupChangeResponse>, ApiError> {
+    log::debug!("Color request for group {}", query.id);
+
+    let members = groups::members(&state.lights, CollectionKind::Group, query.id).await;
+    if members.is_empty() {
+        return Err(ApiError::UnknownCollection(query.id));
+    }
+
+    let change = color_change(query.color, query.hue, query.saturation, query.brightness, query.kelvin)?;
+
+    let mut skipped = Vec::new();
+    let members = online_members(members, &mut skipped)?;
 
     let lights = state.lights.read().await;
-    
-    let mut light = lights.get(&body.ip).unwrap().write().await;
-    
-    light.label = Some(body.name.clone());
 
-    drop(light);
+    // parts of the color the request leaves out are kept per light
+    let mut requests = Vec::new();
+    let mut unsupported = Vec::new();
+    for Member { serial, address, .. } in members {
+        let Some(light) = lights.get(&serial) else {
+            continue;
+        };
+        let light = light.read().await;
+
+        let target = change.apply(light.hsbk().unwrap_or(DEFAULT_COLOR));
+
+        if let Some(capabilities) = &light.capabilities {
+            if let Err(reason) = capabilities.check_color(target.saturation, target.kelvin) {
+                log::debug!("Skipping {} in group {}: {}", serial, query.id, reason);
+                unsupported.push(SkippedLight { serial, reason });
+                continue;
+            }
+        }
+
+        requests.push((serial, address, Message::SetColor {
+            reserved_6: 1,
+            hue: target.hue,
+            saturation: target.saturation,
+            brightness: target.brightness,
+            kelvin: target.kelvin,
+            duration_ms: query.duration_ms.unwrap_or(DEFAULT_COLOR_DURATION_MS),
+        }));
+    }
+
     drop(lights);
 
-    let mut guard = state.last_req_sequence.lock().await;
-
-    let sequence = *guard;
-    *guard = guard.wrapping_add(1);
-
-    drop(guard);
-
-    // pad name to 32 bytes with null bytes
-    let mut label = body.name.clone();
-    label.push_str(&"\x00".repeat(32 - body.name.len()));
-
-    state.tx.send(Request {
-        options: LifxRequestOptions {
-            tagged: true,
-            source: 0,
-            target: [0; 8],
-            ack_required: false,
-            res_required: true,
-            sequence,
-        },
-        message: Message::SetLabel { label },
-        target: body.ip.clone(),
-    }).unwrap();
-    
-    return;
-}
\ No newline at end of file
+    // no light in the group can show the color, so there's nothing to report as done
+    if requests.is_empty() {
+        if let Some(first) = unsupported.first() {
+            let reasons: Vec<String> = unsupported.iter().map(|light| format!("{}: {}", light.serial, light.reason)).collect();
+
+            return Err(ApiError::Unsupported { serial: first.serial, reason: reasons.join(", ") });
+        }
+    }
+
+    skipped.extend(unsupported);
+
+    change_group(&state, query.id, requests, skipped).await
+}
+
+// writes a group or location to each of the lights
+async fn assign(state: &AppState, kind: CollectionKind, membership: Membership, serials: &[Serial]) -> Result<Json<Collection>, ApiError> {
+    let message = kind
+        .set_message(&membership)
+        .ok_or_else(|| ApiError::InvalidLabel(format!("labels can be at most {} bytes", LABEL_SIZE)))?;
+
+    let lights = state.lights.read().await;
+
+    let mut requests = Vec::new();
+    for serial in serials {
+        let light = lights.get(serial).ok_or(ApiError::UnknownLight(*serial))?;
+        let address = light.read().await.address.ok_or(ApiError::LightUnreachable(*serial))?;
+
+        requests.push((*serial, address, message.clone()));
+    }
+
+    drop(lights);
+
+    if let Some((_, e)) = deliver_to_lights(state, requests).await.into_iter().next() {
+        return Err(e);
+    }
+
+    collection_snapshot(state, kind, membership.id).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateCollectionRequest {
+    label: String,
+    lights: Vec<Serial>,
+}
+
+async fn create_collection(state: &AppState, kind: CollectionKind, body: &CreateCollectionRequest) -> Result<Json<Collection>, ApiError> {
+    if body.lights.is_empty() {
+        return Err(ApiError::InvalidValue("a new group or location needs at least one light".to_string()));
+    }
+
+    let membership = Membership::new(CollectionId::generate(), body.label.clone());
+
+    assign(state, kind, membership, &body.lights).await
+}
+
+#[derive(Deserialize)]
+pub struct RenameCollectionRequest {
+    id: CollectionId,
+    label: String,
+}
+
+// every light in it gets the new label, with a newer timestamp so it wins over the old one
+async fn rename_collection(state: &AppState, kind: CollectionKind, body: &RenameCollectionRequest) -> Result<Json<Collection>, ApiError> {
+    let collection = groups::collection(&state.lights, kind, body.id).await.ok_or(ApiError::UnknownCollection(body.id))?;
+
+    let membership = Membership::new(body.id, body.label.clone());
+
+    assign(state, kind, membership, &collection.lights).await
+}
+
+#[derive(Deserialize)]
+pub struct MoveToCollectionRequest {
+    id: CollectionId,
+    lights: Vec<Serial>,
+}
+
+async fn move_to_collection(state: &AppState, kind: CollectionKind, body: &MoveToCollectionRequest) -> Result<Json<Collection>, ApiError> {
+    let collection = groups::collection(&state.lights, kind, body.id).await.ok_or(ApiError::UnknownCollection(body.id))?;
+
+    let membership = Membership::new(body.id, collection.label);
+
+    assign(state, kind, membership, &body.lights).await
+}
+
+pub async fn create_group(state: State<AppState>, body: Json<CreateCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Create group request for {}", body.label);
+
+    create_collection(&state, CollectionKind::Group, &body).await
+}
+
+pub async fn rename_group(state: State<AppState>, body: Json<RenameCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Rename group request for {}", body.id);
+
+    rename_collection(&state, CollectionKind::Group, &body).await
+}
+
+pub async fn move_to_group(state: State<AppState>, body: Json<MoveToCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Move to group request for {}", body.id);
+
+    move_to_collection(&state, CollectionKind::Group, &body).await
+}
+
+pub async fn create_location(state: State<AppState>, body: Json<CreateCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Create location request for {}", body.label);
+
+    create_collection(&state, CollectionKind::Location, &body).await
+}
+
+pub async fn rename_location(state: State<AppState>, body: Json<RenameCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Rename location request for {}", body.id);
+
+    rename_collection(&state, CollectionKind::Location, &body).await
+}
+
+pub async fn move_to_location(state: State<AppState>, body: Json<MoveToCollectionRequest>) -> Result<Json<Collection>, ApiError> {
+    log::debug!("Move to location request for {}", body.id);
+
+    move_to_collection(&state, CollectionKind::Location, &body).await
+}
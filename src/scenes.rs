@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{color::Hsbk, now_ms, storage::write_atomic, Serial};
+
+// how one light looks in a scene
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SceneLight {
+    pub serial: Serial,
+    pub on: bool,
+    pub color: Hsbk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    pub lights: Vec<SceneLight>,
+
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+impl Scene {
+    pub fn new(name: String, lights: Vec<SceneLight>) -> Self {
+        let now = now_ms();
+
+        Scene {
+            name,
+            lights,
+            created_at_ms: now,
+            updated_at_ms: now,
+        }
+    }
+}
+
+// scenes by name, written to a JSON file on every change
+pub struct SceneStore {
+    path: PathBuf,
+    scenes: RwLock<BTreeMap<String, Scene>>,
+}
+
+impl SceneStore {
+    // a missing file is an empty store
+    pub fn load(path: PathBuf) -> Self {
+        let scenes = match std::fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<Vec<Scene>>(&contents) {
+                Ok(scenes) => {
+                    log::info!("Loaded {} scenes from {}", scenes.len(), path.display());
+                    scenes.into_iter().map(|scene| (scene.name.clone(), scene)).collect()
+                }
+                Err(e) => {
+                    log::error!("Ignoring unreadable scenes file {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                log::error!("Failed to read scenes from {}: {}", path.display(), e);
+                BTreeMap::new()
+            }
+        };
+
+        SceneStore {
+            path,
+            scenes: RwLock::new(scenes),
+        }
+    }
+
+    // changes are made to a copy which only replaces the scenes in memory once it's saved, so a failed
+    // write changes nothing
+    async fn save(&self, scenes: &BTreeMap<String, Scene>) -> io::Result<()> {
+        write_atomic(&self.path, &scenes.values().collect::<Vec<_>>()).await
+    }
+
+    pub async fn list(&self) -> Vec<Scene> {
+        self.scenes.read().await.values().cloned().collect()
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Scene> {
+        self.scenes.read().await.get(name).cloned()
+    }
+
+    // false if a scene with that name already exists
+    pub async fn insert(&self, scene: Scene) -> io::Result<bool> {
+        let mut scenes = self.scenes.write().await;
+
+        if scenes.contains_key(&scene.name) {
+            return Ok(false);
+        }
+
+        let mut changed = scenes.clone();
+        changed.insert(scene.name.clone(), scene);
+        self.save(&changed).await?;
+
+        *scenes = changed;
+
+        Ok(true)
+    }
+
+    // replaces the scene stored under `name`, which may rename it. None if there's no such scene,
+    // Some(false) if renaming it would overwrite another one
+    pub async fn update(&self, name: &str, scene: Scene) -> io::Result<Option<bool>> {
+        let mut scenes = self.scenes.write().await;
+
+        if !scenes.contains_key(name) {
+            return Ok(None);
+        }
+
+        if scene.name != name && scenes.contains_key(&scene.name) {
+            return Ok(Some(false));
+        }
+
+        let mut changed = scenes.clone();
+        changed.remove(name);
+        changed.insert(scene.name.clone(), scene);
+        self.save(&changed).await?;
+
+        *scenes = changed;
+
+        Ok(Some(true))
+    }
+
+    pub async fn remove(&self, name: &str) -> io::Result<Option<Scene>> {
+        let mut scenes = self.scenes.write().await;
+
+        let mut changed = scenes.clone();
+
+        let removed = changed.remove(name);
+        if removed.is_some() {
+            self.save(&changed).await?;
+            *scenes = changed;
+        }
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::{HashMap, VecDeque}, time::{Duration, Instant}};
+
+use lifx_lan::Message;
+
+use crate::{Request, Serial};
+
+// LIFX recommend sending no more than 20 messages per second to a single device
+pub const DEFAULT_MAX_MESSAGES_PER_SECOND: u32 = 20;
+
+// a request ready to go out, along with any queued requests it replaced
+pub struct Outbound {
+    pub request: Request,
+    pub superseded: Vec<Request>,
+}
+
+#[derive(PartialEq, Eq)]
+enum Coalesce {
+    Color,
+    Power,
+}
+
+// changes where only the most recent one matters, so an unsent older one can be dropped
+fn coalesce_kind(message: &Message) -> Option<Coalesce> {
+    match message {
+        Message::SetColor { .. } => Some(Coalesce::Color),
+        Message::SetPower { .. } | Message::SetLightPower { .. } => Some(Coalesce::Power),
+        _ => None,
+    }
+}
+
+struct LightQueue {
+    pending: VecDeque<Outbound>,
+    next_send_at: Instant,
+}
+
+// paces unicast requests per light, merging pending state changes of the same kind
+pub struct OutboundScheduler {
+    interval: Duration,
+    queues: HashMap<Serial, LightQueue>,
+}
+
+impl OutboundScheduler {
+    pub fn new(max_messages_per_second: u32) -> Self {
+        OutboundScheduler {
+            interval: Duration::from_secs(1) / max_messages_per_second.max(1),
+            queues: HashMap::new(),
+        }
+    }
+
+    // lights already waiting out the old interval keep to it until their next message goes out
+    pub fn set_max_messages_per_second(&mut self, max_messages_per_second: u32) {
+        self.interval = Duration::from_secs(1) / max_messages_per_second.max(1);
+    }
+
+    pub fn enqueue(&mut self, serial: Serial, request: Request) {
+        let queue = self.queues.entry(serial).or_insert_with(|| LightQueue {
+            pending: VecDeque::new(),
+            next_send_at: Instant::now(),
+        });
+
+        if let Some(kind) = coalesce_kind(&request.message) {
+            let existing = queue
+                .pending
+                .iter_mut()
+                .find(|outbound| coalesce_kind(&outbound.request.message).as_ref() == Some(&kind));
+
+            if let Some(outbound) = existing {
+                log::debug!("Replacing unsent {:?} for {} with a newer one", outbound.request.message, serial);
+
+                let replaced = std::mem::replace(&mut outbound.request, request);
+                outbound.superseded.push(replaced);
+
+                return;
+            }
+        }
+
+        queue.pending.push_back(Outbound {
+            request,
+            superseded: Vec::new(),
+        });
+    }
+
+    pub fn next_ready_at(&self) -> Option<Instant> {
+        self.queues
+            .values()
+            .filter(|queue| !queue.pending.is_empty())
+            .map(|queue| queue.next_send_at)
+            .min()
+    }
+
+    // takes a send slot for something that doesn't go through the queue, such as a retransmission.
+    // Err says when the light can next be sent to
+    pub fn claim(&mut self, serial: Serial, now: Instant) -> Result<(), Instant> {
+        let queue = self.queues.entry(serial).or_insert_with(|| LightQueue {
+            pending: VecDeque::new(),
+            next_send_at: now,
+        });
+
+        if queue.next_send_at > now {
+            return Err(queue.next_send_at);
+        }
+
+        queue.next_send_at = now + self.interval;
+
+        Ok(())
+    }
+
+    // takes at most one request from each light whose rate allows sending now
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<Outbound> {
+        let mut ready = Vec::new();
+
+        for queue in self.queues.values_mut() {
+            if queue.next_send_at > now {
+                continue;
+            }
+
+            if let Some(outbound) = queue.pending.pop_front() {
+                queue.next_send_at = now + self.interval;
+                ready.push(outbound);
+            }
+        }
+
+        // idle lights don't need to be remembered once their interval has passed
+        self.queues.retain(|_, queue| !queue.pending.is_empty() || queue.next_send_at > now);
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lifx_lan::LifxRequestOptions;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    const LIGHT: Serial = Serial([1; 6]);
+
+    fn request(message: Message) -> Request {
+        Request {
+            options: LifxRequestOptions {
+                tagged: false,
+                source: 1234,
+                target: LIGHT.to_target(),
+                ack_required: false,
+                res_required: true,
+                sequence: 1,
+            },
+            message,
+            target: "192.168.1.20:56700".to_string(),
+            ack: None,
+            response: None,
+            probe: false,
+        }
+    }
+
+    fn set_color(hue: u16) -> Message {
+        Message::SetColor { reserved_6: 1, hue, saturation: 65535, brightness: 65535, kelvin: 3500, duration_ms: 0 }
+    }
+
+    #[test]
+    fn newer_color_replaces_an_unsent_one_in_its_place() {
+        let mut scheduler = OutboundScheduler::new(10);
+
+        scheduler.enqueue(LIGHT, request(set_color(1)));
+        scheduler.enqueue(LIGHT, request(Message::GetLabel));
+        scheduler.enqueue(LIGHT, request(set_color(2)));
+
+        let now = Instant::now();
+
+        let first = scheduler.pop_ready(now);
+        assert_eq!(first.len(), 1);
+        assert!(matches!(first[0].request.message, Message::SetColor { hue: 2, .. }));
+        assert!(matches!(first[0].superseded[..], [Request { message: Message::SetColor { hue: 1, .. }, .. }]));
+
+        let second = scheduler.pop_ready(now + Duration::from_millis(100));
+        assert!(matches!(second[..], [Outbound { request: Request { message: Message::GetLabel, .. }, .. }]));
+    }
+
+    #[test]
+    fn replaced_request_keeps_its_senders() {
+        let mut scheduler = OutboundScheduler::new(10);
+
+        let (ack_tx, _ack_rx) = oneshot::channel();
+        let (response_tx, _response_rx) = oneshot::channel();
+        scheduler.enqueue(LIGHT, Request { ack: Some(ack_tx), response: Some(response_tx), ..request(set_color(1)) });
+        scheduler.enqueue(LIGHT, request(set_color(2)));
+
+        let ready = scheduler.pop_ready(Instant::now());
+        let superseded = &ready[0].superseded[0];
+
+        assert!(superseded.ack.is_some());
+        assert!(superseded.response.is_some());
+    }
+
+    #[test]
+    fn power_messages_coalesce_together() {
+        let mut scheduler = OutboundScheduler::new(10);
+
+        scheduler.enqueue(LIGHT, request(Message::SetPower { level: 65535 }));
+        scheduler.enqueue(LIGHT, request(Message::SetLightPower { level: 0, duration_ms: 500 }));
+
+        let ready = scheduler.pop_ready(Instant::now());
+        assert_eq!(ready.len(), 1);
+        assert!(matches!(ready[0].request.message, Message::SetLightPower { level: 0, .. }));
+        assert_eq!(ready[0].superseded.len(), 1);
+
+        assert_eq!(scheduler.next_ready_at(), None);
+    }
+
+    #[test]
+    fn pop_ready_waits_out_the_interval() {
+        let mut scheduler = OutboundScheduler::new(10);
+
+        scheduler.enqueue(LIGHT, request(Message::GetLabel));
+        scheduler.enqueue(LIGHT, request(Message::GetColor));
+        scheduler.enqueue(Serial([2; 6]), request(Message::GetLabel));
+
+        let now = Instant::now();
+
+        // one from each light straight away
+        assert_eq!(scheduler.pop_ready(now).len(), 2);
+
+        assert!(scheduler.pop_ready(now + Duration::from_millis(50)).is_empty());
+        assert_eq!(scheduler.next_ready_at(), Some(now + Duration::from_millis(100)));
+        assert_eq!(scheduler.pop_ready(now + Duration::from_millis(100)).len(), 1);
+    }
+
+    #[test]
+    fn claim_shares_the_interval_with_queued_requests() {
+        let mut scheduler = OutboundScheduler::new(10);
+        let now = Instant::now();
+
+        assert_eq!(scheduler.claim(LIGHT, now), Ok(()));
+        assert_eq!(scheduler.claim(LIGHT, now), Err(now + Duration::from_millis(100)));
+
+        scheduler.enqueue(LIGHT, request(Message::GetLabel));
+        assert!(scheduler.pop_ready(now).is_empty());
+        assert_eq!(scheduler.pop_ready(now + Duration::from_millis(100)).len(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+use std::str::FromStr;
+
+use crate::{groups::Membership, Light, Lights, Serial};
+
+// one part of a selector, in the LIFX HTTP API's grammar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectorPart {
+    All,
+    Id(Serial),
+    Label(String),
+    Group(String),
+    GroupId(String),
+    Location(String),
+    LocationId(String),
+}
+
+// picks lights like "all", "label:Kitchen" or "group:Upstairs,id:d073d5001234", a light matching any part is selected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector(pub Vec<SelectorPart>);
+
+impl SelectorPart {
+    fn matches(&self, light: &Light) -> bool {
+        match self {
+            SelectorPart::All => true,
+            SelectorPart::Id(serial) => light.serial == *serial,
+            SelectorPart::Label(label) => light.label.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(label)),
+            SelectorPart::Group(label) => membership_label_is(light.group.as_ref(), label),
+            SelectorPart::GroupId(id) => membership_id_is(light.group.as_ref(), id),
+            SelectorPart::Location(label) => membership_label_is(light.location.as_ref(), label),
+            SelectorPart::LocationId(id) => membership_id_is(light.location.as_ref(), id),
+        }
+    }
+}
+
+fn membership_label_is(membership: Option<&Membership>, label: &str) -> bool {
+    membership.is_some_and(|membership| membership.label.eq_ignore_ascii_case(label))
+}
+
+// ids are compared without the dashes, as the HTTP API writes them as plain hex
+fn membership_id_is(membership: Option<&Membership>, id: &str) -> bool {
+    let id: String = id.chars().filter(|c| *c != '-').collect();
+
+    membership.is_some_and(|membership| {
+        let own: String = membership.id.to_string().chars().filter(|c| *c != '-').collect();
+        own.eq_ignore_ascii_case(&id)
+    })
+}
+
+impl FromStr for SelectorPart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s == "all" {
+            return Ok(SelectorPart::All);
+        }
+
+        let (kind, value) = s.split_once(':').ok_or_else(|| format!("invalid selector: {}", s))?;
+
+        if value.is_empty() {
+            return Err(format!("empty value in selector: {}", s));
+        }
+
+        match kind {
+            "id" => Ok(SelectorPart::Id(value.parse()?)),
+            "label" => Ok(SelectorPart::Label(value.to_string())),
+            "group" => Ok(SelectorPart::Group(value.to_string())),
+            "group_id" => Ok(SelectorPart::GroupId(value.to_string())),
+            "location" => Ok(SelectorPart::Location(value.to_string())),
+            "location_id" => Ok(SelectorPart::LocationId(value.to_string())),
+            _ => Err(format!("unknown selector type: {}", kind)),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(',').map(str::parse).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Selector(parts))
+    }
+}
+
+impl Selector {
+    pub fn matches(&self, light: &Light) -> bool {
+        self.0.iter().any(|part| part.matches(light))
+    }
+
+    // snapshots of the selected lights, ordered by serial
+    pub async fn select(&self, lights: &Lights) -> Vec<Light> {
+        let lights = lights.read().await;
+
+        let mut selected = Vec::new();
+
+        for light in lights.values() {
+            let light = light.read().await;
+
+            if self.matches(&light) {
+                selected.push(light.clone());
+            }
+        }
+
+        drop(lights);
+
+        selected.sort_by_key(|light| light.serial.0);
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::groups::CollectionId;
+
+    use super::*;
+
+    const SERIAL: Serial = Serial([0xd0, 0x73, 0xd5, 0x00, 0x12, 0x34]);
+    const GROUP_ID: CollectionId = CollectionId([0xab; 16]);
+    const LOCATION_ID: CollectionId = CollectionId([0xcd; 16]);
+
+    fn light() -> Light {
+        let mut light = Light::new(SERIAL);
+        light.label = Some("Kitchen".to_string());
+        light.group = Some(Membership { id: GROUP_ID, label: "Downstairs".to_string(), updated_at: 1 });
+        light.location = Some(Membership { id: LOCATION_ID, label: "Home".to_string(), updated_at: 1 });
+
+        light
+    }
+
+    fn selects(selector: &str, light: &Light) -> bool {
+        selector.parse::<Selector>().unwrap().matches(light)
+    }
+
+    #[test]
+    fn parses_each_kind_of_part() {
+        assert_eq!("all".parse(), Ok(SelectorPart::All));
+        assert_eq!("id:d073d5001234".parse(), Ok(SelectorPart::Id(SERIAL)));
+        assert_eq!("label:Kitchen".parse(), Ok(SelectorPart::Label("Kitchen".to_string())));
+        assert_eq!("group:Downstairs".parse(), Ok(SelectorPart::Group("Downstairs".to_string())));
+        assert_eq!("group_id:abab".parse(), Ok(SelectorPart::GroupId("abab".to_string())));
+        assert_eq!("location:Home".parse(), Ok(SelectorPart::Location("Home".to_string())));
+        assert_eq!("location_id:cdcd".parse(), Ok(SelectorPart::LocationId("cdcd".to_string())));
+    }
+
+    #[test]
+    fn parses_a_comma_separated_list() {
+        assert_eq!(
+            "label:Kitchen, id:d073d5001234".parse(),
+            Ok(Selector(vec![SelectorPart::Label("Kitchen".to_string()), SelectorPart::Id(SERIAL)]))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        assert!("label:".parse::<SelectorPart>().is_err());
+        assert!("id:".parse::<SelectorPart>().is_err());
+        assert!("id:nonsense".parse::<SelectorPart>().is_err());
+        assert!("Kitchen".parse::<SelectorPart>().is_err());
+        assert!("scene:Evening".parse::<SelectorPart>().is_err());
+        assert!("".parse::<Selector>().is_err());
+        assert!("all,".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn matches_each_kind_of_part() {
+        let light = light();
+
+        assert!(selects("all", &light));
+        assert!(selects("id:d073d5001234", &light));
+        assert!(selects("label:kitchen", &light));
+        assert!(selects("group:downstairs", &light));
+        assert!(selects(&format!("group_id:{}", GROUP_ID), &light));
+        assert!(selects(&format!("group_id:{}", "ab".repeat(16)), &light));
+        assert!(selects("location:HOME", &light));
+        assert!(selects(&format!("location_id:{}", "CD".repeat(16)), &light));
+
+        assert!(!selects("id:d073d5005678", &light));
+        assert!(!selects("label:Bedroom", &light));
+        assert!(!selects("group:Home", &light));
+        assert!(!selects(&format!("group_id:{}", LOCATION_ID), &light));
+        assert!(!selects("location:Downstairs", &light));
+        assert!(!selects(&format!("location_id:{}", GROUP_ID), &light));
+    }
+
+    #[test]
+    fn list_matches_when_any_part_does() {
+        let light = light();
+
+        assert!(selects("label:Bedroom,group:Downstairs", &light));
+        assert!(!selects("label:Bedroom,group:Upstairs", &light));
+    }
+
+    #[test]
+    fn light_without_a_label_or_group_only_matches_all_and_its_id() {
+        let light = Light::new(SERIAL);
+
+        assert!(selects("all", &light));
+        assert!(selects("id:d073d5001234", &light));
+        assert!(!selects("label:Kitchen", &light));
+        assert!(!selects("group:Downstairs", &light));
+        assert!(!selects(&format!("location_id:{}", LOCATION_ID), &light));
+    }
+}
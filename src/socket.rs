@@ -1,202 +1,620 @@
-use std::{collections::HashMap, io, net::SocketAddr, sync::{atomic::AtomicBool, Arc}, time::Duration};
+use std::{
+    collections::HashMap, net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4}, sync::{atomic::{AtomicBool, Ordering}, mpsc::RecvTimeoutError, Arc}, time::{Duration, Instant},
+};
 
-use lifx_lan::{deserialize_lifx_packet, serialize_lifx_packet, Message};
+use lifx_lan::{deserialize_lifx_packet, Message};
 use socket2::{Domain, Protocol, Socket, Type};
-use tokio::{net::UdpSocket, sync::RwLock, task::JoinHandle, time::sleep};
+use tokio::{net::UdpSocket, select, sync::{mpsc, watch, RwLock}, task::JoinHandle, time::sleep_until};
 
-use crate::{Light, Request};
+use crate::{ack::AckTracker, config::{Config, LIFX_PORT}, events::{EventBus, LightEvent}, groups::{CollectionId, Membership}, interfaces::{select_interfaces, LanInterface}, packet::{self, MAX_PACKET_SIZE}, response::{ReplyKind, ResponseTracker}, scheduler::{Outbound, OutboundScheduler}, now_ms, Light, LightStatus, Lights, Request, Serial};
 
-const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:56700";
+// how long after a probe a reply from that address counts as answering it
+const PROBE_REPLY_WINDOW: Duration = Duration::from_secs(10);
+// how often the request forwarder checks whether we're shutting down
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
-pub fn create_socket(lights: Arc<RwLock<HashMap<String, Arc<RwLock<Light>>>>>, is_terminating: Arc<AtomicBool>) -> (std::sync::mpsc::Sender<Request>, JoinHandle<()>) {
-    let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+// a socket bound to a single interface, or to every interface when none could be selected
+struct InterfaceSocket {
+    interface: Option<LanInterface>,
+    socket: Arc<UdpSocket>,
+    // stopped along with the socket when it's replaced
+    receiver: JoinHandle<()>,
+}
+
+impl InterfaceSocket {
+    fn new(interface: Option<LanInterface>, socket: UdpSocket, packets: &mpsc::UnboundedSender<ReceivedPacket>) -> Self {
+        let socket = Arc::new(socket);
+        let name = interface.as_ref().map(|interface| interface.name.clone());
+
+        InterfaceSocket {
+            interface,
+            receiver: tokio::spawn(receive_packets(name, socket.clone(), packets.clone())),
+            socket,
+        }
+    }
+}
+
+impl Drop for InterfaceSocket {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+struct ReceivedPacket {
+    // name of the interface the socket it arrived on is bound to
+    interface: Option<String>,
+    data: Vec<u8>,
+    src: SocketAddr,
+}
+
+fn bind_socket(address: Ipv4Addr, port: u16) -> std::io::Result<UdpSocket> {
+    let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+    sock.set_nonblocking(true)?;
+    sock.set_broadcast(true)?;
+
+    // the official LIFX apps listen on 56700 too, sharing it is the only way to bind it alongside them.
+    // any other port is ours alone, so nothing else gets handed our replies
+    if port == LIFX_PORT {
+        sock.set_reuse_address(true)?;
+        sock.set_reuse_port(true)?;
+    }
+
+    sock.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(address, port));
+    sock.bind(&addr.into())?;
+
+    UdpSocket::from_std(sock.into())
+}
+
+// a socket per interface, reusing any in `previous` already bound to an unchanged interface so its port and
+// the replies on their way to it survive. the sockets that weren't reused are left in `previous`
+fn bind_sockets(interfaces: Vec<LanInterface>, port: u16, previous: &mut Vec<InterfaceSocket>, packets: &mpsc::UnboundedSender<ReceivedPacket>) -> Vec<InterfaceSocket> {
+    let mut sockets = Vec::new();
+
+    for interface in interfaces {
+        if let Some(index) = previous.iter().position(|socket| socket.interface.as_ref() == Some(&interface)) {
+            sockets.push(previous.swap_remove(index));
+            continue;
+        }
+
+        match bind_socket(interface.address, port) {
+            Ok(socket) => {
+                log::info!("Listening on {} ({}:{}).", interface.name, interface.address, socket.local_addr().map(|addr| addr.port()).unwrap_or(port));
 
-    sock.set_nonblocking(true).unwrap();
-    sock.set_broadcast(true).unwrap();
-    sock.set_reuse_address(true).unwrap();
-    sock.set_reuse_port(true).unwrap();
+                sockets.push(InterfaceSocket::new(Some(interface), socket, packets));
+            }
+            Err(e) => log::error!("Failed to bind socket on {} ({}): {}", interface.name, interface.address, e),
+        }
+    }
+
+    if sockets.is_empty() {
+        if let Some(index) = previous.iter().position(|socket| socket.interface.is_none()) {
+            sockets.push(previous.swap_remove(index));
+            return sockets;
+        }
+
+        log::warn!("No usable network interfaces selected, listening on all interfaces.");
+
+        match bind_socket(Ipv4Addr::UNSPECIFIED, port) {
+            Ok(socket) => sockets.push(InterfaceSocket::new(None, socket, packets)),
+            Err(e) => log::error!("Failed to bind socket on all interfaces: {}", e),
+        }
+    }
+
+    sockets
+}
+
+// binds the sockets and starts the task that sends requests queued on the returned channel
+pub fn create_socket(lights: Lights, events: EventBus, is_terminating: Arc<AtomicBool>, source: u32, mut config: watch::Receiver<Config>, interfaces: watch::Receiver<Vec<LanInterface>>) -> (std::sync::mpsc::Sender<Request>, JoinHandle<()>) {
+    let (port, interface_filter, max_messages_per_second) = {
+        let config = config.borrow_and_update();
 
-    sock.set_read_timeout(Some(Duration::from_secs(10)))
-        .unwrap();
+        (config.lan.bind_port, config.lan.interface_filter(), config.lan.max_messages_per_second)
+    };
 
-    let addr: SocketAddr = DEFAULT_LISTEN_ADDRESS.parse().unwrap();
-    sock.bind(&addr.into()).unwrap();
+    let (packets_tx, packets_rx) = mpsc::unbounded_channel();
+
+    let sockets = bind_sockets(select_interfaces(&interface_filter), port, &mut Vec::new(), &packets_tx);
+    assert!(!sockets.is_empty(), "no socket could be bound");
 
-    let socket = UdpSocket::from_std(sock.into()).unwrap();
     let (tx, rx) = std::sync::mpsc::channel::<Request>();
+    let (forward_tx, forward_rx) = mpsc::unbounded_channel::<Request>();
+
+    tokio::task::spawn_blocking(move || forward_requests(rx, forward_tx, is_terminating));
+
+    let handler = SocketHandler {
+        sockets,
+        port,
+        packets: packets_tx,
+        lights,
+        events,
+        source,
+        request_buffer: [0u8; MAX_PACKET_SIZE],
+        acks: AckTracker::new(),
+        responses: ResponseTracker::new(),
+        scheduler: OutboundScheduler::new(max_messages_per_second),
+        probed: HashMap::new(),
+    };
 
-    let handle = tokio::spawn(handle_socket(socket, rx, lights, is_terminating));
+    let handle = tokio::spawn(handle_socket(handler, packets_rx, forward_rx, config, interfaces));
     log::info!("Socket handler thread started.");
 
     return (tx, handle);
 }
 
-pub async fn handle_socket(socket: UdpSocket, rx: std::sync::mpsc::Receiver<Request>, lights: Arc<RwLock<HashMap<String, Arc<RwLock<Light>>>>>, is_terminating: Arc<AtomicBool>) {
-    let mut message_buffer = [0u8; 128];
-    let mut request_buffer = [0u8; 128];
+// std's receiver can't be awaited, so requests are handed over to the socket loop from a blocking thread.
+// it stops once every sender is gone or we're shutting down, which closes the loop's channel and ends it
+fn forward_requests(rx: std::sync::mpsc::Receiver<Request>, forward: mpsc::UnboundedSender<Request>, is_terminating: Arc<AtomicBool>) {
+    while !is_terminating.load(Ordering::Acquire) {
+        match rx.recv_timeout(SHUTDOWN_CHECK_INTERVAL) {
+            Ok(request) => {
+                if forward.send(request).is_err() {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+// forwards datagrams from one socket to the handler, tagged with the interface they arrived on
+async fn receive_packets(interface: Option<String>, socket: Arc<UdpSocket>, packets: mpsc::UnboundedSender<ReceivedPacket>) {
+    let mut message_buffer = [0u8; MAX_PACKET_SIZE];
 
     loop {
-        loop {
-            if (*is_terminating).load(std::sync::atomic::Ordering::Acquire) {
-                return;
+        match socket.recv_from(&mut message_buffer).await {
+            Ok((size, src)) => {
+                let packet = ReceivedPacket {
+                    interface: interface.clone(),
+                    data: message_buffer[..size].to_vec(),
+                    src,
+                };
+
+                if packets.send(packet).is_err() {
+                    return;
+                }
             }
+            Err(e) => eprintln!("Failed to receive a datagram: {}", e),
+        }
+    }
+}
+
+// discovery publishes the interfaces whenever they change, the sockets follow them
+async fn handle_socket(mut handler: SocketHandler, mut packets_rx: mpsc::UnboundedReceiver<ReceivedPacket>, mut rx: mpsc::UnboundedReceiver<Request>, mut config: watch::Receiver<Config>, mut interfaces: watch::Receiver<Vec<LanInterface>>) {
+    loop {
+        let next_retry_at = handler.acks.next_retry_at();
+        let next_send_at = handler.scheduler.next_ready_at();
 
-            match socket.try_recv_from(&mut message_buffer) {
-                Ok((_size, src)) => {
-                    let (_header, payload) = match deserialize_lifx_packet(&message_buffer) {
-                        Ok(header_payload) => header_payload,
-                        Err(e) => {
-                            eprintln!("Failed to deserialize packet: {}", e,);
-                            continue;
+        select! {
+            Some(received) = packets_rx.recv() => {
+                handler.handle_packet(&received.data, received.src, received.interface.as_deref()).await;
+            }
+            request = rx.recv() => {
+                match request {
+                    Some(request) => {
+                        // a burst sent right after the interfaces changed has to go out through the new sockets
+                        if interfaces.has_changed().unwrap_or(false) {
+                            handler.rebind(interfaces.borrow_and_update().clone());
                         }
-                    };
 
-                    log::debug!("Received message from {}: {:?}", src, payload);
+                        handler.queue_request(request).await;
+                    }
+                    // every sender is gone or we're shutting down
+                    None => break,
+                }
+            }
+            _ = sleep_until(next_retry_at.unwrap_or_else(Instant::now).into()), if next_retry_at.is_some() => {
+                handler.retransmit().await;
+            }
+            _ = sleep_until(next_send_at.unwrap_or_else(Instant::now).into()), if next_send_at.is_some() => {
+                handler.send_ready().await;
+            }
+            Ok(()) = config.changed() => {
+                let max_messages_per_second = config.borrow_and_update().lan.max_messages_per_second;
+                handler.scheduler.set_max_messages_per_second(max_messages_per_second);
+            }
+            Ok(()) = interfaces.changed() => {
+                handler.rebind(interfaces.borrow_and_update().clone());
+            }
+        }
+    }
+}
 
-                    match payload {
-                        Message::StateService { service, port: _ } => {
-                            if service == 1 {
-                                log::debug!("Got UDP Service advertisement from {}", src);
+#[derive(PartialEq, Eq)]
+enum Origin {
+    // a reply to one of our requests
+    Ours,
+    // traffic between a device and another LIFX client on the network
+    Observed,
+}
 
-                                {
-                                    let lights = lights.read().await;
+struct SocketHandler {
+    sockets: Vec<InterfaceSocket>,
+    // kept to bind new sockets with when the interfaces change
+    port: u16,
+    packets: mpsc::UnboundedSender<ReceivedPacket>,
 
-                                    if lights.contains_key(&src.to_string()) {
-                                        continue;
-                                    }
-                                }
+    lights: Lights,
+    events: EventBus,
 
-                                let mut lights = lights.write().await;
+    // our instance's source id, replies carrying any other were requested by someone else
+    source: u32,
 
-                                if !lights.contains_key(&src.to_string()) {
-                                    lights.insert(
-                                        src.to_string(),
-                                        Arc::new(RwLock::new(Light::default())),
-                                    );
-                                }
-                            }
-                        }
-                        Message::Label { label } => {
-                            log::debug!("Got label from {}: {}", src, label);
-
-                            let mut lights = lights.write().await;
-
-                            if let Some(light) = lights.get_mut(&src.to_string()) {
-                                light.write().await.label = Some(label);
-                            } else {
-                                lights.insert(
-                                    src.to_string(),
-                                    Arc::new(RwLock::new(Light {
-                                        label: Some(label),
-                                        ..Light::default()
-                                    })),
-                                );
-                            }
-                        }
-                        Message::HostFirmware {
-                            build,
-                            version_minor,
-                            version_major,
-                            ..
-                        } => {
-                            let mut lights = lights.write().await;
-
-                            if let Some(light) = lights.get_mut(&src.to_string()) {
-                                light.write().await.firmware_version = Some(format!(
-                                    "{}.{}.{}",
-                                    build, version_major, version_minor
-                                ));
-                            } else {
-                                lights.insert(
-                                    src.to_string(),
-                                    Arc::new(RwLock::new(Light {
-                                        firmware_version: Some(format!(
-                                            "{}.{}.{}",
-                                            build, version_major, version_minor
-                                        )),
-                                        ..Light::default()
-                                    })),
-                                );
-                            }
-                        }
-                        Message::LightState {
-                            hue,
-                            saturation,
-                            brightness,
-                            kelvin,
-                            power,
-                            label,
-                            ..
-                        } => {
-                            let mut lights = lights.write().await;
-
-                            if let Some(light) = lights.get_mut(&src.to_string()) {
-                                let mut light = light.write().await;
-
-                                light.label = Some(label);
-                                light.hue = Some(hue);
-                                light.saturation = Some(saturation);
-                                light.brightness = Some(brightness);
-                                light.kelvin = Some(kelvin);
-                                light.power = Some(power);
-                            } else {
-                                lights.insert(
-                                    src.to_string(),
-                                    Arc::new(RwLock::new(Light {
-                                        label: Some(label),
-                                        hue: Some(hue),
-                                        saturation: Some(saturation),
-                                        brightness: Some(brightness),
-                                        kelvin: Some(kelvin),
-                                        power: Some(power),
-                                        ..Light::default()
-                                    })),
-                                );
-                            }
-                        }
-                        _ => {}
-                    }
+    request_buffer: [u8; MAX_PACKET_SIZE],
+
+    acks: AckTracker,
+    responses: ResponseTracker,
+    scheduler: OutboundScheduler,
+
+    // addresses of configured hosts we've probed, and when
+    probed: HashMap<IpAddr, Instant>,
+}
+
+impl SocketHandler {
+    async fn handle_packet(&mut self, received: &[u8], src: SocketAddr, interface: Option<&str>) {
+        let lights = &self.lights;
+
+        let frame = match packet::received_frame(received) {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::debug!("Dropping malformed packet from {}: {}", src, e);
+                return;
+            }
+        };
+
+        let (header, payload) = match deserialize_lifx_packet(frame) {
+            Ok(header_payload) => header_payload,
+            Err(e) => {
+                eprintln!("Failed to deserialize packet: {}", e,);
+                return;
+            }
+        };
+
+        log::debug!("Received message from {}: {:?}", src, payload);
+
+        let serial = Serial::from_target(&header.target);
+        if serial.is_zero() {
+            // our own broadcasts, or a packet not sent by a device
+            return;
+        }
+
+        let origin = if header.source == self.source {
+            Origin::Ours
+        } else {
+            log::debug!("Observed {:?} from {} for source {}", payload, serial, header.source);
+            Origin::Observed
+        };
+
+        // observed replies still tell us about the device, but never answer our own requests
+        let responders = match (&origin, &payload) {
+            (Origin::Observed, _) | (_, Message::Acknowledgement { .. }) => Vec::new(),
+            (Origin::Ours, _) => self.responses.take(serial, header.sequence, &payload),
+        };
+        let response = if responders.is_empty() { None } else { Some(payload.clone()) };
+
+        // any packet from a device proves it's alive, whoever it was meant for
+        let (light, previous) = get_or_insert_light(lights, serial, src, interface).await;
+
+        if origin == Origin::Ours && self.answers_probe(src) {
+            light.write().await.manually_configured = true;
+        }
+
+        match payload {
+            Message::Acknowledgement { .. } => {
+                if origin == Origin::Observed {
+                    return;
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    break;
+
+                if !self.acks.acknowledge(serial, header.sequence) {
+                    log::debug!("Got unexpected acknowledgement {} from {}", header.sequence, serial);
                 }
-                Err(e) => {
-                    eprintln!("Failed to receive a datagram: {}", e);
-                    break;
+            }
+            Message::StateService { service, port: _ } => {
+                if service == 1 {
+                    log::debug!("Got UDP Service advertisement from {} ({})", serial, src);
                 }
             }
+            Message::Label { label } => {
+                log::debug!("Got label from {} ({}): {}", serial, src, label);
+
+                light.write().await.label = Some(label);
+            }
+            Message::HostFirmware {
+                build,
+                version_minor,
+                version_major,
+                ..
+            } => {
+                let mut light = light.write().await;
+
+                light.firmware_version = Some(format!(
+                    "{}.{}.{}",
+                    build, version_major, version_minor
+                ));
+                light.firmware_major = Some(version_major as u16);
+                light.firmware_minor = Some(version_minor as u16);
+                light.refresh_product();
+            }
+            Message::LightState {
+                hue,
+                saturation,
+                brightness,
+                kelvin,
+                power,
+                label,
+                ..
+            } => {
+                let mut light = light.write().await;
+
+                light.label = Some(label);
+                light.hue = Some(hue);
+                light.saturation = Some(saturation);
+                light.brightness = Some(brightness);
+                light.kelvin = Some(kelvin);
+                light.power = Some(power);
+            }
+            Message::StateVersion { vendor, product, .. } => {
+                let mut light = light.write().await;
+
+                light.vendor = Some(vendor);
+                light.product = Some(product);
+                light.refresh_product();
+            }
+            Message::StateGroup { group, label, updated_at } => {
+                light.write().await.group = Some(Membership {
+                    id: CollectionId(group),
+                    label: label.trim_end_matches('\0').to_string(),
+                    updated_at,
+                });
+            }
+            Message::StateLocation { location, label, updated_at } => {
+                light.write().await.location = Some(Membership {
+                    id: CollectionId(location),
+                    label: label.trim_end_matches('\0').to_string(),
+                    updated_at,
+                });
+            }
+            Message::StatePower { level } | Message::StateLightPower { level } => {
+                light.write().await.power = Some(level);
+            }
+            _ => {}
         }
 
-        if (*is_terminating).load(std::sync::atomic::Ordering::Acquire) {
+        self.publish_change(previous, &light).await;
+
+        // answered only once the cache reflects the reply, so the requester sees the confirmed state
+        if let Some(response) = response {
+            // the reply we were waiting for implies the request arrived, even if its acknowledgement didn't
+            self.acks.acknowledge(serial, header.sequence);
+
+            for responder in responders {
+                let _ = responder.send(response.clone());
+            }
+        }
+    }
+
+    // tells subscribers about a new light, or one whose state the packet changed
+    async fn publish_change(&self, previous: Option<Light>, light: &Arc<RwLock<Light>>) {
+        let current = light.read().await.clone();
+
+        match previous {
+            None => self.events.publish(LightEvent::Found { light: current }),
+            Some(mut previous) => {
+                // being seen again isn't a change on its own
+                previous.last_seen_ms = current.last_seen_ms;
+
+                if previous != current {
+                    self.events.publish(LightEvent::Changed { light: current });
+                }
+            }
+        }
+    }
+
+    fn answers_probe(&self, src: SocketAddr) -> bool {
+        self.probed.get(&src.ip()).is_some_and(|probed_at| probed_at.elapsed() < PROBE_REPLY_WINDOW)
+    }
+
+    // unicast requests to a light are paced by the scheduler, everything else goes straight out
+    async fn queue_request(&mut self, request: Request) {
+        let serial = Serial::from_target(&request.options.target);
+
+        if request.options.tagged || serial.is_zero() {
+            self.send_request(Outbound { request, superseded: Vec::new() }).await;
             return;
         }
 
-        loop {
-            if (*is_terminating).load(std::sync::atomic::Ordering::Acquire) {
-                return;
+        self.scheduler.enqueue(serial, request);
+        self.send_ready().await;
+    }
+
+    async fn send_ready(&mut self) {
+        for outbound in self.scheduler.pop_ready(Instant::now()) {
+            self.send_request(outbound).await;
+        }
+    }
+
+    async fn send_request(&mut self, outbound: Outbound) {
+        let Outbound { mut request, superseded } = outbound;
+
+        // replaced requests are answered by whatever the device says about the newer one
+        let mut acks: Vec<_> = request.ack.take().into_iter().collect();
+        let mut responders: Vec<_> = request.response.take().into_iter().collect();
+
+        for mut replaced in superseded {
+            acks.extend(replaced.ack.take());
+            responders.extend(replaced.response.take());
+        }
+
+        if !acks.is_empty() {
+            request.options.ack_required = true;
+        }
+
+        let packet = packet::serialize(
+            &request.options,
+            &request.message,
+            &mut self.request_buffer,
+        ).to_vec();
+
+        let interface = self.light_interface(&request).await;
+        let socket = self.socket_for(&request.target, interface.as_deref());
+
+        match socket.send_to(&packet, &request.target).await {
+            Ok(_) => {
+                log::debug!("Sent message to {}: {:?}", &request.target, &request.message);
             }
+            Err(e) => eprintln!("Failed to send message: {}", e),
+        }
 
-            match rx.try_recv() {
-                Ok(request) => {
-                    serialize_lifx_packet(
-                        &request.options,
-                        &request.message,
-                        &mut request_buffer,
-                    );
+        self.responses.remove_abandoned();
 
-                    match socket.send_to(&request_buffer, &request.target).await {
-                        Ok(_) => {
-                            log::debug!("Sent message to {}: {:?}", &request.target, &request.message);
-                        }
-                        Err(e) => eprintln!("Failed to send message: {}", e),
-                    }
-                }
-                Err(_) => {
-                    break;
-                }
+        if request.probe {
+            if let Ok(target) = request.target.parse::<SocketAddr>() {
+                let now = Instant::now();
+
+                self.probed.retain(|_, probed_at| now.duration_since(*probed_at) < PROBE_REPLY_WINDOW);
+                self.probed.insert(target.ip(), now);
             }
         }
 
-        sleep(Duration::from_millis(20)).await;
+        if !responders.is_empty() {
+            match ReplyKind::expected_for(&request.message) {
+                Some(expects) => self.responses.track(Serial::from_target(&request.options.target), request.options.sequence, expects, responders),
+                // dropping the responders fails the request straight away rather than at its timeout
+                None => log::error!("{:?} has no reply to wait for", request.message),
+            }
+        }
+
+        // a failed send is retried like a lost packet
+        if !acks.is_empty() {
+            self.acks.track(
+                Serial::from_target(&request.options.target),
+                request.options.sequence,
+                packet,
+                request.target.clone(),
+                interface,
+                acks,
+            );
+        }
+    }
+
+    // the interface a light was found on, for requests addressed to a specific light
+    async fn light_interface(&self, request: &Request) -> Option<String> {
+        let serial = Serial::from_target(&request.options.target);
+        if request.options.tagged || serial.is_zero() {
+            return None;
+        }
+
+        let light = self.lights.read().await.get(&serial).cloned()?;
+        let interface = light.read().await.interface.clone();
+
+        interface
     }
-}
\ No newline at end of file
+
+    // a socket left bound to an address the host no longer has can't send, so sockets are rebound whenever an
+    // interface comes, goes or changes address. sockets on unchanged interfaces are kept as they are
+    fn rebind(&mut self, interfaces: Vec<LanInterface>) {
+        let mut previous = std::mem::take(&mut self.sockets);
+        let sockets = bind_sockets(interfaces, self.port, &mut previous, &self.packets);
+
+        if sockets.is_empty() {
+            log::error!("No socket could be bound after the network interfaces changed, keeping the old ones");
+            self.sockets = previous;
+            return;
+        }
+
+        for socket in &previous {
+            match &socket.interface {
+                Some(interface) => log::info!("Stopped listening on {} ({}).", interface.name, interface.address),
+                None => log::info!("Stopped listening on all interfaces."),
+            }
+        }
+
+        self.sockets = sockets;
+    }
+
+    // prefers the light's own interface, then whichever interface's subnet holds the target
+    fn socket_for(&self, target: &str, interface: Option<&str>) -> &UdpSocket {
+        let by_name = interface.and_then(|name| {
+            self.sockets
+                .iter()
+                .find(|socket| socket.interface.as_ref().is_some_and(|interface| interface.name == name))
+        });
+
+        let by_subnet = || match target.parse::<SocketAddr>() {
+            Ok(SocketAddr::V4(target)) => self
+                .sockets
+                .iter()
+                .find(|socket| socket.interface.as_ref().is_some_and(|interface| interface.contains(*target.ip()))),
+            _ => None,
+        };
+
+        &by_name.or_else(by_subnet).unwrap_or(&self.sockets[0]).socket
+    }
+
+    // retries count against the light's rate limit the same as queued requests
+    async fn retransmit(&mut self) {
+        let now = Instant::now();
+        let scheduler = &mut self.scheduler;
+
+        for (packet, target, interface) in self.acks.due_retransmissions(now, |serial| scheduler.claim(serial, now)) {
+            let socket = self.socket_for(&target, interface.as_deref());
+
+            match socket.send_to(&packet, &target).await {
+                Ok(_) => log::debug!("Retransmitted message to {}", target),
+                Err(e) => log::warn!("Failed to retransmit message to {}: {}", target, e),
+            }
+        }
+    }
+}
+
+// looks up a light by serial, registering it if it's new, and records where it was last heard from
+// also returns the light's state from before this packet, None if it's new
+async fn get_or_insert_light(lights: &Lights, serial: Serial, src: SocketAddr, interface: Option<&str>) -> (Arc<RwLock<Light>>, Option<Light>) {
+    let existing = lights.read().await.get(&serial).cloned();
+
+    let (light, is_new) = match existing {
+        Some(light) => (light, false),
+        None => {
+            let mut lights = lights.write().await;
+            let mut is_new = false;
+
+            let light = lights
+                .entry(serial)
+                .or_insert_with(|| {
+                    log::info!("Discovered new light {} at {}", serial, src);
+                    is_new = true;
+                    Arc::new(RwLock::new(Light::new(serial)))
+                })
+                .clone();
+
+            (light, is_new)
+        }
+    };
+
+    let previous = {
+        let mut light = light.write().await;
+        let previous = if is_new { None } else { Some(light.clone()) };
+
+        if light.address != Some(src) {
+            if let Some(previous) = light.address {
+                log::info!("Light {} moved from {} to {}", serial, previous, src);
+            }
+
+            light.address = Some(src);
+        }
+
+        light.last_seen_ms = Some(now_ms());
+        light.status = LightStatus::Online;
+
+        if light.interface.as_deref() != interface {
+            light.interface = interface.map(str::to_string);
+        }
+
+        previous
+    };
+
+    (light, previous)
+}
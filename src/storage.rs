@@ -0,0 +1,12 @@
+use std::{io, path::Path};
+
+use serde::Serialize;
+
+// written to a temporary file next to `path` and renamed over it, so a crash mid-write leaves the old file intact
+pub async fn write_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    let contents = serde_json::to_string_pretty(value)?;
+
+    let temporary = path.with_extension("json.tmp");
+    tokio::fs::write(&temporary, contents).await?;
+    tokio::fs::rename(&temporary, path).await
+}
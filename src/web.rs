@@ -1,50 +1,76 @@
-use std::{collections::HashMap, sync::{mpsc::Sender, Arc}};
+use std::sync::Arc;
 
 use axum::{extract::Request, http::{header, HeaderValue}, middleware::{self, Next}, response::Response, routing::{get, post}, Router};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::watch;
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, services::ServeDir};
 
-use crate::{routes::{color, get_lights, power, set_name, trigger_onboarding}, Light};
+use crate::{api_v1, client::LifxClient, config::Config, discovery::DiscoveryHandle, events::EventBus, lifx_http, routes::{
+    color, create_group, create_location, discover, get_groups, get_lights, get_locations, group_color, group_power, group_toggle_power, move_to_group,
+    move_to_location, power, rename_group, rename_location, set_name, toggle_power, trigger_onboarding,
+}, scenes::SceneStore, ws::websocket, Lights};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub lights: Arc<RwLock<HashMap<String, Arc<RwLock<Light>>>>>,
-    pub tx: Sender<crate::Request>,
-
-    pub last_req_sequence: Arc<Mutex<u8>>,
+    pub lights: Lights,
+    pub client: LifxClient,
+    pub discovery: DiscoveryHandle,
+    pub events: EventBus,
+    pub scenes: Arc<SceneStore>,
+    pub config: watch::Receiver<Config>,
 }
 
-pub async fn start_webserver(tx: std::sync::mpsc::Sender<crate::Request>, lights: Arc<RwLock<HashMap<String, Arc<RwLock<Light>>>>>) {
+pub async fn start_webserver(client: LifxClient, discovery: DiscoveryHandle, events: EventBus, scenes: Arc<SceneStore>, lights: Lights, config: watch::Receiver<Config>) {
+    // the web settings need a restart to change, so they're only read here
+    let (address, static_dir) = {
+        let config = config.borrow();
+
+        (config.web_address(), config.web.static_dir.clone())
+    };
+
     let state = AppState {
         lights: lights.clone(),
-        tx: tx.clone(),
-        last_req_sequence: Arc::new(Mutex::new(0)),
+        client,
+        discovery,
+        events,
+        scenes,
+        config,
     };
 
     let app: Router = Router::new()
         .nest_service("/", ServiceBuilder::new()
             .layer(middleware::from_fn(set_static_cache_control))
             .service(
-                ServeDir::new("static/",
+                ServeDir::new(static_dir,
             ))
         )
         .route("/api/lights", get(get_lights))
         .route("/api/setPower", post(power))
+        .route("/api/togglePower", post(toggle_power))
         .route("/api/setColor", post(color))
         .route("/api/setName", post(set_name))
         .route("/api/onboard", post(trigger_onboarding))
+        .route("/api/discover", post(discover))
+        .route("/api/ws", get(websocket))
+        .route("/api/groups", get(get_groups).post(create_group))
+        .route("/api/groups/rename", post(rename_group))
+        .route("/api/groups/move", post(move_to_group))
+        .route("/api/groups/setPower", post(group_power))
+        .route("/api/groups/togglePower", post(group_toggle_power))
+        .route("/api/groups/setColor", post(group_color))
+        .route("/api/locations", get(get_locations).post(create_location))
+        .route("/api/locations/rename", post(rename_location))
+        .route("/api/locations/move", post(move_to_location))
+        .nest("/api/v1", api_v1::router())
+        .nest("/v1", lifx_http::router())
         .layer(
             CorsLayer::permissive(),
         )
         .with_state(state);
 
-    let address = std::env::var("WEB_LISTEN_ADDRESS").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port = std::env::var("WEB_LISTEN_PORT").unwrap_or_else(|_| "3000".to_string());
-
-    log::info!("Starting webserver on http://{}:{}", address, port);
+    log::info!("Starting webserver on http://{}", address);
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", address, port)).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
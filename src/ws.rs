@@ -0,0 +1,162 @@
+use axum::{extract::{ws::{Message as WsMessage, WebSocket, WebSocketUpgrade}, State}, response::Response};
+use serde::{Deserialize, Serialize};
+use tokio::{select, sync::{broadcast::error::RecvError, mpsc}};
+
+use crate::{
+    color::ColorChange, error::ApiError, events::LightEvent, routes::{color_change, light_address, set_light_color, set_light_power, toggle_light_power},
+    web::AppState, Light, Serial,
+};
+
+// what a client can ask for over the socket, answered with a result or error carrying the same request_id
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Control {
+    SetPower {
+        id: Serial,
+        on: bool,
+        duration_ms: Option<u32>,
+    },
+    TogglePower {
+        id: Serial,
+        duration_ms: Option<u32>,
+    },
+    SetColor {
+        id: Serial,
+        color: Option<ColorChange>,
+        hue: Option<u16>,
+        saturation: Option<u16>,
+        brightness: Option<u16>,
+        kelvin: Option<u16>,
+        duration_ms: Option<u32>,
+    },
+}
+
+#[derive(Deserialize)]
+struct ControlMessage {
+    // echoed back so the client can match answers to requests
+    request_id: Option<serde_json::Value>,
+
+    #[serde(flatten)]
+    control: Control,
+}
+
+// everything sent to the client besides light events, which are sent as they are
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    // every known light, sent on connecting and whenever this client missed events
+    Snapshot { lights: Vec<Light> },
+    Result { request_id: Option<serde_json::Value> },
+    Error {
+        request_id: Option<serde_json::Value>,
+        error: &'static str,
+        message: String,
+    },
+}
+
+pub async fn websocket(state: State<AppState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| handle_websocket(state.0, socket))
+}
+
+async fn snapshot(state: &AppState) -> Reply {
+    let lights = state.lights.read().await;
+
+    let mut snapshot = Vec::with_capacity(lights.len());
+    for light in lights.values() {
+        snapshot.push(light.read().await.clone());
+    }
+
+    Reply::Snapshot { lights: snapshot }
+}
+
+async fn send_json<T: Serialize>(socket: &mut WebSocket, value: &T) -> bool {
+    let text = match serde_json::to_string(value) {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Failed to serialize WebSocket message: {}", e);
+            return true;
+        }
+    };
+
+    socket.send(WsMessage::Text(text)).await.is_ok()
+}
+
+async fn handle_websocket(state: AppState, mut socket: WebSocket) {
+    // subscribe before the snapshot so nothing falls between them
+    let mut events = state.events.subscribe();
+
+    if !send_json(&mut socket, &snapshot(&state).await).await {
+        return;
+    }
+
+    // controls run in their own tasks so a slow light doesn't hold up events
+    let (replies_tx, mut replies_rx) = mpsc::unbounded_channel();
+
+    loop {
+        select! {
+            event = events.recv() => {
+                let sent = match event {
+                    Ok(event) => send_json::<LightEvent>(&mut socket, &event).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        log::debug!("WebSocket client missed {} events, resending state", missed);
+                        send_json(&mut socket, &snapshot(&state).await).await
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if !sent {
+                    break;
+                }
+            }
+            Some(reply) = replies_rx.recv() => {
+                if !send_json::<Reply>(&mut socket, &reply).await {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        let replies = replies_tx.clone();
+
+                        match serde_json::from_str::<ControlMessage>(&text) {
+                            Ok(message) => {
+                                let state = state.clone();
+
+                                tokio::spawn(async move {
+                                    let reply = match run_control(&state, message.control).await {
+                                        Ok(()) => Reply::Result { request_id: message.request_id },
+                                        Err(e) => Reply::Error { request_id: message.request_id, error: e.code(), message: e.to_string() },
+                                    };
+
+                                    let _ = replies.send(reply);
+                                });
+                            }
+                            Err(e) => {
+                                let _ = replies.send(Reply::Error { request_id: None, error: "invalid_message", message: e.to_string() });
+                            }
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    // pings are answered by axum, binary messages aren't part of the protocol
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+async fn run_control(state: &AppState, control: Control) -> Result<(), ApiError> {
+    match control {
+        Control::SetPower { id, on, duration_ms } => {
+            let address = light_address(state, id).await?;
+
+            set_light_power(state, id, address, on, duration_ms).await
+        }
+        Control::TogglePower { id, duration_ms } => toggle_light_power(state, id, duration_ms).await,
+        Control::SetColor { id, color, hue, saturation, brightness, kelvin, duration_ms } => {
+            let change = color_change(color, hue, saturation, brightness, kelvin)?;
+
+            set_light_color(state, id, change, duration_ms).await
+        }
+    }
+}
@@ -66,13 +66,11 @@ function populateLights(data) {
         lightCard.querySelector('#color-picker').addEventListener('color-changed', (event) => {
             const color = event.detail.value;
 
-            const [r, g, b] = hexToRgb(color);
-            const { h, s, v } = RGBtoHSV(r, g, b);
-            const kelvin = light.kelvin; 
-
-            console.log(`HSL: ${h}, ${s}, ${v}`);
+            if (sendControl({ type: 'set_color', id: ip, color })) {
+                return;
+            }
 
-            fetch(`/api/setColor?ip=${ip}&hue=${Math.floor(h * 65535)}&saturation=${Math.floor(s * 65535)}&brightness=${Math.floor(v * 65535)}&kelvin=${kelvin}`, {
+            fetch(`/api/setColor?id=${ip}&color=${encodeURIComponent(color)}`, {
                 method: 'POST',
             })
                 .then(response => response.json())
@@ -85,8 +83,12 @@ function populateLights(data) {
 }
 
 async function togglePower(lightIp) {
+    if (sendControl({ type: 'toggle_power', id: lightIp })) {
+        return;
+    }
+
     try {
-        const response = await fetch(`/api/setPower?ip=${lightIp}`, {
+        const response = await fetch(`/api/togglePower?id=${lightIp}`, {
             method: 'POST',
         });
 
@@ -100,23 +102,62 @@ async function togglePower(lightIp) {
     }
 }
 
-async function refreshLightData() {
-    try {
-        // disable cors
-        const response = await fetch('/api/lights', {
-            // mode: 'no-cors'
-            headers: {
-                'Content-Type': 'application/json',
-                'Access-Control-Allow-Origin': '*',
-            }
-        });
+const lights = {};
+let socket = null;
 
-        const data = await response.json();
+// the server pushes a snapshot on connecting, then every change as it happens
+function connectEvents() {
+    const protocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+    socket = new WebSocket(`${protocol}://${window.location.host}/api/ws`);
 
-        populateLights(data);
-    } catch (error) {
-        console.error('Error fetching light data:', error);
+    socket.addEventListener('message', (event) => {
+        const message = JSON.parse(event.data);
+
+        switch (message.type) {
+            case 'snapshot':
+                for (const serial of Object.keys(lights)) {
+                    delete lights[serial];
+                }
+                for (const light of message.lights) {
+                    lights[light.serial] = light;
+                }
+                for (const card of document.querySelectorAll('.light-card')) {
+                    if (!(card.id in lights)) {
+                        card.remove();
+                    }
+                }
+                break;
+            case 'found':
+            case 'changed':
+            case 'offline':
+                lights[message.light.serial] = message.light;
+                break;
+            case 'removed':
+                delete lights[message.serial];
+                document.getElementById(message.serial)?.remove();
+                break;
+            case 'error':
+                console.error('Request failed:', message.message);
+                return;
+            default:
+                return;
+        }
+
+        populateLights(lights);
+    });
+
+    // reconnect if the server restarts, the snapshot catches up on anything missed
+    socket.addEventListener('close', () => setTimeout(connectEvents, 1000));
+}
+
+// sends a control message over the socket, false when it isn't connected
+function sendControl(control) {
+    if (!socket || socket.readyState !== WebSocket.OPEN) {
+        return false;
     }
+
+    socket.send(JSON.stringify(control));
+    return true;
 }
 
 function editLabel(ip) {
@@ -137,7 +178,7 @@ function editLabel(ip) {
                 headers: {
                     'Content-Type': 'application/json'
                 },
-                body: JSON.stringify({ ip: ip, name: newLabel })
+                body: JSON.stringify({ id: ip, name: newLabel })
             })
             .then(() => {
                 const newLabelEl = document.createElement('h2');
@@ -150,7 +191,7 @@ function editLabel(ip) {
     });
 }
 
-setInterval(refreshLightData, 500);
+connectEvents();
 
 function componentToHex(c) {
     var hex = c.toString(16);
@@ -161,38 +202,6 @@ function rgbToHex(r, g, b) {
     return "#" + componentToHex(r) + componentToHex(g) + componentToHex(b);
 }
 
-function hexToRgb(hex) {
-    const bigint = parseInt(hex.slice(1), 16);
-    const r = (bigint >> 16) & 255;
-    const g = (bigint >> 8) & 255;
-    const b = bigint & 255;
-    return [r, g, b];
-}
-
-function RGBtoHSV(r, g, b) {
-    if (arguments.length === 1) {
-        g = r.g, b = r.b, r = r.r;
-    }
-    var max = Math.max(r, g, b), min = Math.min(r, g, b),
-        d = max - min,
-        h,
-        s = (max === 0 ? 0 : d / max),
-        v = max / 255;
-
-    switch (max) {
-        case min: h = 0; break;
-        case r: h = (g - b) + d * (g < b ? 6 : 0); h /= 6 * d; break;
-        case g: h = (b - r) + d * 2; h /= 6 * d; break;
-        case b: h = (r - g) + d * 4; h /= 6 * d; break;
-    }
-
-    return {
-        h: h,
-        s: s,
-        v: v
-    };
-}
-
 // ai
 function HSVtoRGB(h, s, v) {
     var r, g, b, i, f, p, q, t;